@@ -1,8 +1,98 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Script to run, starts the REPL when missing
     pub file: Option<String>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+/// Flags of the commands that run scripts, the REPL, `debug` and `test`.
+#[derive(Args, Debug, Clone)]
+pub struct RunArgs {
+    /// When to color printed values
+    #[arg(long, value_enum, default_value_t = Color::Auto)]
+    pub color: Color,
+
+    /// Highest `??` level to print, `??` is level 1 and shown by default, `???` needs 2 and
+    /// so on, 0 hides them all
+    #[arg(long, default_value_t = 1)]
+    pub debug_level: usize,
+
+    /// Stop after this many statements, loop iterations and calls
+    #[arg(long)]
+    pub max_steps: Option<u64>,
+
+    /// Deepest allowed closure call nesting
+    #[arg(long, default_value_t = 256)]
+    pub max_depth: usize,
+
+    /// Largest value in bytes a variable or operation may produce
+    #[arg(long)]
+    pub max_value_size: Option<usize>,
+
+    /// Stop after this many milliseconds
+    #[arg(long)]
+    pub timeout: Option<u64>,
+
+    /// Make undefined variables, undeclared assignments, wrong argument counts and
+    /// mismatched operand types errors
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Record every value given to the listed variables, or to all of them without a list,
+    /// for `history(name)` and the REPL's :history
+    #[arg(long, num_args = 0.., value_delimiter = ',', require_equals = true)]
+    pub history: Option<Vec<String>>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a script one statement at a time, with breakpoints and watches
+    Debug {
+        file: String,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Format scripts in place, or standard input to standard output without files
+    Fmt {
+        files: Vec<String>,
+        /// Only list the files that would change, failing if there are any
+        #[arg(long)]
+        check: bool,
+    },
+    /// Check scripts, or standard input without files, for likely mistakes
+    Lint {
+        files: Vec<String>,
+        /// Fail on warnings too, not only on errors
+        #[arg(long)]
+        deny_warnings: bool,
+        /// List the rules and exit
+        #[arg(long)]
+        rules: bool,
+    },
+    /// Serve the language server protocol over stdin and stdout, for editors
+    Lsp,
+    /// Run the `test` blocks of scripts, directories are searched for .qs files
+    Test {
+        /// Defaults to the current directory
+        paths: Vec<String>,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Never,
+    Auto,
+    Always,
 }
 
 pub fn parse() -> Cli {
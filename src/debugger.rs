@@ -0,0 +1,304 @@
+use qs::{
+    parser::{self, Closure, Span, Value},
+    runtime::{self, Hook, Printable, Result, Runtime, Storage},
+};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    process::exit,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+const PROMPT: &str = "(qsdb) ";
+
+const HELP: &str = "\
+s, step            run to the next statement
+n, next            run to the next statement outside of calls made by this one
+c, continue        run to the next breakpoint
+b, break <line>    stop before the statement at <line>, or at <file>:<line>
+d, delete <line>   remove a breakpoint, all of them without <line>
+w, watch <expr>    show <expr> at every stop
+unwatch <n>        stop showing watch <n>
+p, print <expr>    evaluate <expr> where the program stopped
+vars               list variables in every scope
+bt                 list the calls in progress
+l, list            show the source around the current line
+h, help            show this message
+q, quit            stop the program
+Anything else is evaluated like print, an empty line repeats the last command.";
+
+/// Set by ctrl-c to stop at the next statement.
+static PAUSE: AtomicBool = AtomicBool::new(false);
+
+enum Mode {
+    Step,
+    /// Stop once the call depth is back to at most this.
+    Next(usize),
+    Continue,
+}
+
+struct Breakpoint {
+    /// `None` for the debugged file, otherwise matched against the end of the source name.
+    file: Option<String>,
+    line: usize,
+}
+
+impl Breakpoint {
+    fn hit(&self, span: &Span, main: &str) -> bool {
+        span.line == self.line
+            && match &self.file {
+                Some(file) => span.source.ends_with(file.as_str()),
+                None => span.source == main,
+            }
+    }
+}
+
+struct Debugger {
+    editor: DefaultEditor,
+    mode: Mode,
+    main: String,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<String>,
+    /// Lines of every file seen so far, by source name.
+    sources: HashMap<String, Vec<String>>,
+    /// Last statement reached in each frame, the ones below the top are call sites.
+    positions: Vec<Span>,
+    last_command: String,
+}
+
+/// Runs `file` under the debugger, stopping before its first statement.
+pub fn run(mut runtime: Runtime, file: &str) {
+    let src = read_to_string(file).unwrap_or_else(|e| {
+        eprintln!("can't read {}: {}", file, e);
+        exit(1);
+    });
+    let program = parser::parse_named(&src, file).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    ctrlc::set_handler(|| PAUSE.store(true, Ordering::SeqCst)).unwrap();
+
+    let debugger = Debugger {
+        editor: DefaultEditor::new().unwrap(),
+        mode: Mode::Step,
+        main: file.to_string(),
+        breakpoints: vec![],
+        watches: vec![],
+        sources: HashMap::from([(file.to_string(), src.lines().map(str::to_string).collect())]),
+        positions: vec![],
+        last_command: String::new(),
+    };
+    runtime.set_hook(Some(Box::new(debugger)));
+    match runtime.run(program) {
+        Ok(value) => println!("program finished with {}", value.fmt_print()),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    }
+}
+
+impl Hook for Debugger {
+    fn before(&mut self, span: &Span, storage: &mut Storage) -> Result<()> {
+        self.positions.truncate(storage.frames.len());
+        self.positions.push(span.clone());
+
+        let stop = PAUSE.swap(false, Ordering::SeqCst)
+            || self.breakpoints.iter().any(|b| b.hit(span, &self.main))
+            || match self.mode {
+                Mode::Step => true,
+                Mode::Next(depth) => storage.budget.depth() <= depth,
+                Mode::Continue => false,
+            };
+        if !stop {
+            return Ok(());
+        }
+
+        self.show_location(span, storage, 0);
+        self.show_locals(storage);
+        self.show_watches(storage);
+        loop {
+            let line = match self.editor.readline(PROMPT) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    println!("type q to quit");
+                    continue;
+                }
+                Err(_) => exit(0),
+            };
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => {
+                    let _ = self.editor.add_history_entry(line);
+                    line.to_string()
+                }
+            };
+            self.last_command = line.clone();
+            let (command, arg) = line
+                .split_once(char::is_whitespace)
+                .map(|(command, arg)| (command, arg.trim()))
+                .unwrap_or((&line, ""));
+
+            match command {
+                "s" | "step" => {
+                    self.mode = Mode::Step;
+                    return Ok(());
+                }
+                "n" | "next" => {
+                    self.mode = Mode::Next(storage.budget.depth());
+                    return Ok(());
+                }
+                "c" | "continue" => {
+                    self.mode = Mode::Continue;
+                    return Ok(());
+                }
+                "b" | "break" => self.add_breakpoint(arg),
+                "d" | "delete" => self.delete_breakpoint(arg),
+                "w" | "watch" if !arg.is_empty() => {
+                    self.watches.push(arg.to_string());
+                    self.show_watches(storage);
+                }
+                "unwatch" => match arg.parse::<usize>() {
+                    Ok(i) if i < self.watches.len() => {
+                        self.watches.remove(i);
+                    }
+                    _ => println!("no watch {}", arg),
+                },
+                "p" | "print" => print_eval(storage, arg),
+                "vars" => show_vars(storage),
+                "bt" => self.show_stack(storage),
+                "l" | "list" => self.show_location(span, storage, 5),
+                "h" | "help" => println!("{}", HELP),
+                "q" | "quit" => exit(0),
+                _ => print_eval(storage, &line),
+            }
+        }
+    }
+}
+
+impl Debugger {
+    fn lines(&mut self, source: &str) -> &[String] {
+        self.sources.entry(source.to_string()).or_insert_with(|| {
+            read_to_string(source)
+                .map(|src| src.lines().map(str::to_string).collect())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Prints where the program stopped with `context` lines around it.
+    fn show_location(&mut self, span: &Span, storage: &Storage, context: usize) {
+        match storage.frames.last() {
+            Some(frame) => println!("{}:{} in {}", span.source, span.line, frame.name),
+            None => println!("{}:{}", span.source, span.line),
+        }
+        let lines = self.lines(&span.source);
+        let first = span.line.saturating_sub(context).max(1);
+        let last = (span.line + context).min(lines.len());
+        for number in first..=last {
+            let marker = if number == span.line { "=>" } else { "  " };
+            println!("{} {:>4} | {}", marker, number, lines[number - 1]);
+        }
+    }
+
+    /// Variables of the innermost call, or of the whole program at the top level.
+    fn show_locals(&self, storage: &Storage) {
+        let first = storage.frames.last().map_or(0, |frame| frame.scope);
+        for scope in &storage.scopes[first..] {
+            let mut names = scope.names().collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                let value = scope.get(name);
+                if !is_builtin(&value) && name != "_" {
+                    println!("  {} = {}", name, value.fmt_print());
+                }
+            }
+        }
+    }
+
+    fn show_watches(&self, storage: &mut Storage) {
+        for (i, watch) in self.watches.iter().enumerate() {
+            match runtime::eval_in(storage, watch) {
+                Ok(value) => println!("watch {}: {} = {}", i, watch, value.fmt_print()),
+                Err(e) => println!("watch {}: {} = error: {}", i, watch, e),
+            }
+        }
+    }
+
+    fn show_stack(&self, storage: &Storage) {
+        for (i, span) in self.positions.iter().enumerate().rev() {
+            let name = match i {
+                0 => "<top>",
+                i => storage.frames[i - 1].name.as_str(),
+            };
+            println!(
+                "#{} {} at {}:{}",
+                self.positions.len() - 1 - i,
+                name,
+                span.source,
+                span.line
+            );
+        }
+    }
+
+    fn add_breakpoint(&mut self, arg: &str) {
+        let (file, line) = match arg.rsplit_once(':') {
+            Some((file, line)) => (Some(file.to_string()), line),
+            None => (None, arg),
+        };
+        match line.parse() {
+            Ok(line) => {
+                println!(
+                    "breakpoint at {}:{}",
+                    file.as_deref().unwrap_or(&self.main),
+                    line
+                );
+                self.breakpoints.push(Breakpoint { file, line });
+            }
+            Err(_) => println!("usage: break <line> or break <file>:<line>"),
+        }
+    }
+
+    fn delete_breakpoint(&mut self, arg: &str) {
+        if arg.is_empty() {
+            self.breakpoints.clear();
+            return;
+        }
+        match arg.parse::<usize>() {
+            Ok(line) => self.breakpoints.retain(|b| b.line != line),
+            Err(_) => println!("usage: delete <line>"),
+        }
+    }
+}
+
+fn print_eval(storage: &mut Storage, src: &str) {
+    match runtime::eval_in(storage, src) {
+        Ok(value) => println!("{}", value.fmt_print()),
+        Err(e) => println!("error: {}", e),
+    }
+}
+
+/// Every variable, with the scope it's in and the call that scope belongs to.
+fn show_vars(storage: &Storage) {
+    for (depth, scope) in storage.scopes.iter().enumerate() {
+        let frame = storage
+            .frames
+            .iter()
+            .rev()
+            .find(|frame| frame.scope <= depth)
+            .map(|frame| format!(" {}", frame.name))
+            .unwrap_or_default();
+        let mut names = scope.names().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let value = scope.get(name);
+            if !is_builtin(&value) {
+                println!("[{}{}] {} = {}", depth, frame, name, value.fmt_print());
+            }
+        }
+    }
+}
+
+fn is_builtin(value: &Value) -> bool {
+    matches!(value, Value::Closure(Closure::Native(_)))
+}
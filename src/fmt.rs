@@ -0,0 +1,69 @@
+use qs::parser::{self, Unparse};
+use std::{
+    fs,
+    io::{self, Read},
+    process::exit,
+};
+
+/// Formats `files`, or standard input when there are none. Exits with 1 if one of them
+/// doesn't parse or, with `check`, would change.
+pub fn run(files: &[String], check: bool) {
+    if files.is_empty() {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src).unwrap();
+        match format(&src, "<stdin>") {
+            Some(formatted) if check => exit((formatted != src) as i32),
+            Some(formatted) => print!("{}", formatted),
+            None => exit(1),
+        }
+        return;
+    }
+
+    let mut failed = false;
+    for file in files {
+        let src = match fs::read_to_string(file) {
+            Ok(src) => src,
+            Err(e) => {
+                eprintln!("can't read {}: {}", file, e);
+                failed = true;
+                continue;
+            }
+        };
+        let Some(formatted) = format(&src, file) else {
+            failed = true;
+            continue;
+        };
+        if formatted == src {
+            continue;
+        }
+        if check {
+            println!("{} would be reformatted", file);
+            failed = true;
+        } else if let Err(e) = fs::write(file, formatted) {
+            eprintln!("can't write {}: {}", file, e);
+            failed = true;
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+/// `src` formatted, `None` after printing why when it can't be.
+fn format(src: &str, name: &str) -> Option<String> {
+    let formatted = match parser::format_source(src, name) {
+        Ok(formatted) => formatted,
+        Err(e) => {
+            eprintln!("{}", e);
+            return None;
+        }
+    };
+    // comments aside, formatting must give back the same program
+    let before = parser::parse(src).map(|program| program.unparse());
+    let after = parser::parse(&formatted).map(|program| program.unparse());
+    if before.ok() != after.ok() {
+        eprintln!("{}: formatting would change the program, left as is", name);
+        return None;
+    }
+    Some(formatted)
+}
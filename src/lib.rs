@@ -0,0 +1,22 @@
+//! qs, a small dynamically typed language for quick string manipulations.
+//!
+//! ```
+//! let mut runtime = qs::Runtime::new();
+//! runtime.register_fn("double", |args| match args.first() {
+//!     Some(qs::Value::Number(n)) => qs::Value::Number(n * 2.),
+//!     _ => qs::Value::Nil,
+//! });
+//! runtime.set_global("name", "a_b_c");
+//! let parts: Vec<String> = runtime.eval_str("name$'_'").unwrap().try_into().unwrap();
+//! assert_eq!(parts, ["a", "b", "c"]);
+//! ```
+//!
+//! `Runtime::with_std` also defines `assert`, `assert_eq`, `set`, `history` and `print_options`.
+//! Print options, colors, the debug level and `interrupt` are global to the process rather than
+//! kept per runtime.
+
+pub mod parser;
+pub mod runtime;
+
+pub use parser::{parse, Value};
+pub use runtime::{from_value, to_value, Error, HostType, Printable, Result, Runtime};
@@ -0,0 +1,68 @@
+use qs::parser::{
+    analysis::Severity,
+    lint::{lint, RULES},
+};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Read},
+    process::exit,
+};
+
+/// Lints `files`, or standard input when there are none, printing one line per problem.
+/// Exits with 1 if there were errors, or warnings with `deny_warnings`.
+pub fn run(files: &[String], deny_warnings: bool, list_rules: bool, builtins: HashSet<String>) {
+    if list_rules {
+        for (rule, severity, description) in RULES {
+            println!("{:<16} {:<8} {}", rule, label(*severity), description);
+        }
+        return;
+    }
+
+    let sources = match files.is_empty() {
+        true => {
+            let mut src = String::new();
+            io::stdin().read_to_string(&mut src).unwrap();
+            vec![("<stdin>".to_string(), src)]
+        }
+        false => files
+            .iter()
+            .map(|file| {
+                let src = fs::read_to_string(file).unwrap_or_else(|e| {
+                    eprintln!("can't read {}: {}", file, e);
+                    exit(1);
+                });
+                (file.clone(), src)
+            })
+            .collect(),
+    };
+
+    let mut failed = false;
+    for (name, src) in sources {
+        for diagnostic in lint(&src, &builtins) {
+            let before = &src[..diagnostic.range.start];
+            let line = before.matches('\n').count() + 1;
+            let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+            println!(
+                "{}:{}:{}: {}[{}]: {}",
+                name,
+                line,
+                column,
+                label(diagnostic.severity),
+                diagnostic.rule,
+                diagnostic.message
+            );
+            failed |= diagnostic.severity == Severity::Error || deny_warnings;
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+fn label(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+    }
+}
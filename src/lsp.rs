@@ -0,0 +1,344 @@
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{self, Notification as _},
+    request::{self, Request as _},
+    CompletionItem, CompletionItemKind, CompletionParams, CompletionResponse, Diagnostic,
+    DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, NumberOrString, OneOf, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Uri,
+};
+use qs::parser::{
+    analysis::{self, Analysis, Severity, Symbol, SymbolKind},
+    lint,
+};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+/// An open file and what's known about it.
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+struct Server {
+    connection: Connection,
+    /// Names `make_runtime` defines, offered as completions and never undefined.
+    builtins: HashSet<String>,
+    documents: HashMap<Uri, Document>,
+}
+
+/// Serves the language server protocol over stdio until the client exits.
+pub fn run(builtins: HashSet<String>) {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(Default::default()),
+        ..Default::default()
+    };
+    if let Err(e) = connection.initialize(serde_json::to_value(capabilities).unwrap()) {
+        eprintln!("lsp: {}", e);
+        std::process::exit(1);
+    }
+
+    let mut server = Server {
+        connection,
+        builtins,
+        documents: HashMap::new(),
+    };
+    server.main_loop();
+    drop(server);
+    io_threads.join().unwrap();
+}
+
+impl Server {
+    fn main_loop(&mut self) {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(req) => {
+                    if self.connection.handle_shutdown(&req).unwrap_or(true) {
+                        return;
+                    }
+                    let response = self.request(req);
+                    self.connection.sender.send(response.into()).unwrap();
+                }
+                Message::Notification(notification) => self.notification(notification),
+                Message::Response(_) => (),
+            }
+        }
+    }
+
+    fn request(&self, req: Request) -> Response {
+        let result = match req.method.as_str() {
+            request::HoverRequest::METHOD => {
+                params(req.params).and_then(|p| serde_json::to_value(self.hover(p)))
+            }
+            request::GotoDefinition::METHOD => {
+                params(req.params).and_then(|p| serde_json::to_value(self.definition(p)))
+            }
+            request::DocumentSymbolRequest::METHOD => {
+                params(req.params).and_then(|p| serde_json::to_value(self.symbols(p)))
+            }
+            request::Completion::METHOD => {
+                params(req.params).and_then(|p| serde_json::to_value(self.completion(p)))
+            }
+            _ => {
+                return Response::new_err(
+                    req.id,
+                    lsp_server::ErrorCode::MethodNotFound as i32,
+                    format!("unsupported request {}", req.method),
+                )
+            }
+        };
+        match result {
+            Ok(result) => Response::new_ok(req.id, result),
+            Err(e) => Response::new_err(
+                req.id,
+                lsp_server::ErrorCode::InvalidParams as i32,
+                e.to_string(),
+            ),
+        }
+    }
+
+    fn notification(&mut self, notification: Notification) {
+        match notification.method.as_str() {
+            notification::DidOpenTextDocument::METHOD => {
+                if let Ok(p) = params::<DidOpenTextDocumentParams>(notification.params) {
+                    self.update(p.text_document.uri, p.text_document.text);
+                }
+            }
+            notification::DidChangeTextDocument::METHOD => {
+                if let Ok(p) = params::<DidChangeTextDocumentParams>(notification.params) {
+                    // full sync, the last change has the whole text
+                    if let Some(change) = p.content_changes.into_iter().last() {
+                        self.update(p.text_document.uri, change.text);
+                    }
+                }
+            }
+            notification::DidCloseTextDocument::METHOD => {
+                if let Ok(p) = params::<DidCloseTextDocumentParams>(notification.params) {
+                    self.documents.remove(&p.text_document.uri);
+                    self.publish(p.text_document.uri, vec![]);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn update(&mut self, uri: Uri, text: String) {
+        let analysis = analysis::analyze(&text, &self.builtins);
+        // lint's include those of the analysis
+        let diagnostics = lint::lint_analysis(&text, &analysis)
+            .iter()
+            .map(|diagnostic| Diagnostic {
+                range: range(&text, &diagnostic.range),
+                severity: Some(match diagnostic.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                code: Some(NumberOrString::String(diagnostic.rule.to_string())),
+                source: Some("qs".to_string()),
+                message: diagnostic.message.clone(),
+                ..Default::default()
+            })
+            .collect();
+        self.documents
+            .insert(uri.clone(), Document { text, analysis });
+        self.publish(uri, diagnostics);
+    }
+
+    fn publish(&self, uri: Uri, diagnostics: Vec<Diagnostic>) {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        let notification =
+            Notification::new(notification::PublishDiagnostics::METHOD.to_string(), params);
+        self.connection.sender.send(notification.into()).unwrap();
+    }
+
+    /// The document at `uri` with the symbol under `position`, if any.
+    fn symbol_at(&self, uri: &Uri, position: Position) -> Option<(&Document, &Symbol)> {
+        let document = self.documents.get(uri)?;
+        let offset = offset(&document.text, position);
+        let symbol = document.analysis.symbol_at(offset)?;
+        Some((document, &document.analysis.symbols[symbol]))
+    }
+
+    fn hover(&self, p: HoverParams) -> Option<Hover> {
+        let uri = &p.text_document_position_params.text_document.uri;
+        let position = p.text_document_position_params.position;
+        let signature = match self.symbol_at(uri, position) {
+            Some((_, symbol)) => signature(symbol),
+            None => {
+                let document = self.documents.get(uri)?;
+                let word = word_at(&document.text, offset(&document.text, position));
+                if !self.builtins.contains(word) {
+                    return None;
+                }
+                format!("{}: native", word)
+            }
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```qs\n{}\n```", signature),
+            }),
+            range: None,
+        })
+    }
+
+    fn definition(&self, p: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let uri = p.text_document_position_params.text_document.uri;
+        let position = p.text_document_position_params.position;
+        let (document, symbol) = self.symbol_at(&uri, position)?;
+        Some(GotoDefinitionResponse::Scalar(Location {
+            range: range(&document.text, &symbol.range),
+            uri,
+        }))
+    }
+
+    fn symbols(&self, p: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let document = self.documents.get(&p.text_document.uri)?;
+        Some(DocumentSymbolResponse::Nested(document_symbols(
+            document, None,
+        )))
+    }
+
+    fn completion(&self, p: CompletionParams) -> Option<CompletionResponse> {
+        let mut items = self
+            .builtins
+            .iter()
+            .map(|name| CompletionItem {
+                label: name.clone(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                detail: Some("native".to_string()),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let uri = &p.text_document_position.text_document.uri;
+        if let Some(document) = self.documents.get(uri) {
+            let mut seen = HashSet::new();
+            for symbol in &document.analysis.symbols {
+                if self.builtins.contains(&symbol.name) || !seen.insert(&symbol.name) {
+                    continue;
+                }
+                items.push(CompletionItem {
+                    label: symbol.name.clone(),
+                    kind: Some(match symbol.kind {
+                        SymbolKind::Function => CompletionItemKind::FUNCTION,
+                        SymbolKind::Module => CompletionItemKind::MODULE,
+                        SymbolKind::Variable | SymbolKind::Parameter => {
+                            CompletionItemKind::VARIABLE
+                        }
+                    }),
+                    detail: Some(signature(symbol)),
+                    ..Default::default()
+                });
+            }
+        }
+        items.sort_by(|a, b| a.label.cmp(&b.label));
+        Some(CompletionResponse::Array(items))
+    }
+}
+
+fn params<P: serde::de::DeserializeOwned>(params: Value) -> serde_json::Result<P> {
+    serde_json::from_value(params)
+}
+
+/// What hovering a symbol shows, `x: number` or `fn f(a, b)`.
+fn signature(symbol: &Symbol) -> String {
+    match &symbol.params {
+        Some(params) => format!("fn {}({})", symbol.name, params.join(", ")),
+        None => format!(
+            "{}: {}",
+            symbol.name,
+            symbol.value_kind.unwrap_or("unknown")
+        ),
+    }
+}
+
+/// Symbols defined in the function `parent`, or at the top level, parameters left out.
+fn document_symbols(document: &Document, parent: Option<usize>) -> Vec<DocumentSymbol> {
+    let mut symbols = document
+        .analysis
+        .symbols
+        .iter()
+        .enumerate()
+        .filter(|(_, symbol)| symbol.parent == parent && symbol.kind != SymbolKind::Parameter)
+        .collect::<Vec<_>>();
+    // `fn` definitions are found first, as they're hoisted
+    symbols.sort_by_key(|(_, symbol)| symbol.range.start);
+    symbols
+        .into_iter()
+        .map(|(i, symbol)| {
+            let children = match symbol.kind {
+                SymbolKind::Function => Some(document_symbols(document, Some(i))),
+                _ => None,
+            };
+            #[allow(deprecated)]
+            DocumentSymbol {
+                name: symbol.name.clone(),
+                detail: Some(signature(symbol)),
+                kind: match symbol.kind {
+                    SymbolKind::Function => lsp_types::SymbolKind::FUNCTION,
+                    SymbolKind::Module => lsp_types::SymbolKind::MODULE,
+                    SymbolKind::Variable | SymbolKind::Parameter => lsp_types::SymbolKind::VARIABLE,
+                },
+                tags: None,
+                deprecated: None,
+                range: range(&document.text, &symbol.full_range),
+                selection_range: range(&document.text, &symbol.range),
+                children,
+            }
+        })
+        .collect()
+}
+
+/// The identifier around `offset`.
+fn word_at(text: &str, offset: usize) -> &str {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let start = text[..offset].rfind(|c| !is_word(c)).map_or(0, |i| i + 1);
+    let end = text[offset..]
+        .find(|c| !is_word(c))
+        .map_or(text.len(), |i| offset + i);
+    &text[start..end]
+}
+
+/// LSP positions count UTF-16 code units from the start of the line.
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
+
+fn offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= position.character as usize || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn range(text: &str, range: &std::ops::Range<usize>) -> Range {
+    Range::new(position(text, range.start), position(text, range.end))
+}
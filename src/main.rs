@@ -1,16 +1,25 @@
 mod cli;
-mod parser;
-mod runtime;
+mod debugger;
+mod fmt;
+mod lint;
+mod lsp;
+mod repl;
+mod test;
 
-use parser::Value;
-use runtime::Printable;
+use qs::{
+    parser::{self, Value},
+    runtime::{self, Limits, Printable, PrintOptions, Tracking},
+};
 use std::{
+    collections::HashSet,
     fs::read_to_string,
-    io::{stdin, stdout, Write},
+    io::{stdin, stdout, IsTerminal, Write},
+    process::exit,
+    time::Duration,
 };
 
 fn make_runtime() -> runtime::Runtime {
-    let mut runtime = runtime::Runtime::new();
+    let mut runtime = runtime::Runtime::with_std();
 
     runtime.register_fn("cout", |v: Vec<Value>| {
         print!("{}", v.first().unwrap().fmt_print());
@@ -52,25 +61,73 @@ fn make_runtime() -> runtime::Runtime {
 }
 
 fn main() {
+    runtime::start_clock();
     let cli = cli::parse();
-    let mut runtime = make_runtime();
+    let run = match &cli.command {
+        Some(cli::Command::Debug { run, .. } | cli::Command::Test { run, .. }) => run,
+        _ => &cli.run,
+    }
+    .clone();
+    runtime::set_debug_level(run.debug_level);
+    runtime::set_colors(match run.color {
+        cli::Color::Never => false,
+        cli::Color::Auto => stdout().is_terminal(),
+        cli::Color::Always => true,
+    });
+    if let Some((width, _)) = terminal_size::terminal_size() {
+        runtime::set_print_options(PrintOptions {
+            width: Some(width.0 as usize),
+            ..runtime::print_options()
+        });
+    }
+
+    let limits = Limits {
+        steps: run.max_steps,
+        depth: Some(run.max_depth),
+        value_size: run.max_value_size,
+        timeout: run.timeout.map(Duration::from_millis),
+    };
+    let make_runtime = move || {
+        let mut runtime = make_runtime();
+        runtime.set_limits(limits);
+        runtime.set_strict(run.strict);
+        runtime.set_history(match &run.history {
+            None => Tracking::Off,
+            Some(names) if names.is_empty() => Tracking::All,
+            Some(names) => Tracking::Only(names.iter().cloned().collect()),
+        });
+        runtime
+    };
 
-    if let Some(file) = &cli.file {
-        let src = read_to_string(file).unwrap();
-        let prog = parser::parse(&src);
-        #[cfg(debug_assertions)]
-        output_ast(&prog);
-        runtime.run(prog);
-    } else {
-        loop {
-            print!("> ");
-            stdout().flush().unwrap();
-            let mut input = String::new();
-            stdin().read_line(&mut input).unwrap();
-            let prog = parser::parse(&input);
-            let v = runtime.run(prog);
-            println!("{}", v.fmt_print());
+    match (&cli.command, &cli.file) {
+        (Some(cli::Command::Debug { file, .. }), _) => debugger::run(make_runtime(), file),
+        (Some(cli::Command::Fmt { files, check }), _) => fmt::run(files, *check),
+        (Some(cli::Command::Lint { files, deny_warnings, rules }), _) => {
+            lint::run(files, *deny_warnings, *rules, builtins(&make_runtime()))
         }
+        (Some(cli::Command::Lsp), _) => lsp::run(builtins(&make_runtime())),
+        (Some(cli::Command::Test { paths, .. }), _) => test::run(paths, make_runtime),
+        (None, Some(file)) => run_file(make_runtime(), file),
+        (None, None) => repl::Repl::new(Box::new(make_runtime)).run(),
+    }
+}
+
+/// Names `make_runtime` defines, for checking scripts without running them.
+fn builtins(runtime: &runtime::Runtime) -> HashSet<String> {
+    runtime.storage.global_scope().names().cloned().collect()
+}
+
+fn run_file(mut runtime: runtime::Runtime, file: &str) {
+    let src = read_to_string(file).unwrap();
+    let prog = parser::parse_named(&src, file).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    #[cfg(debug_assertions)]
+    output_ast(&prog);
+    if let Err(e) = runtime.run(prog) {
+        eprintln!("error: {}", e);
+        exit(1);
     }
 }
 
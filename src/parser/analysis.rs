@@ -0,0 +1,761 @@
+//! Name resolution over the parse tree, for tools that look at a script without running it.
+
+use super::pest::{is_assignment, parse, Pair, Rule};
+use pest::error::InputLocation;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Byte offsets into the source.
+    pub range: Range<usize>,
+    pub severity: Severity,
+    /// What kind of problem it is, as named in `// lint-ignore: rule` comments.
+    pub rule: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+    Module,
+}
+
+/// A name given a value by `:=`, `let`, `=`, `->`, `fn`, `import` or a parameter list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The name where it's defined.
+    pub range: Range<usize>,
+    /// The whole statement or function defining it.
+    pub full_range: Range<usize>,
+    /// What `type_name` would say about its first value, when that's known without running.
+    pub value_kind: Option<&'static str>,
+    /// Parameters of `fn` definitions, as written.
+    pub params: Option<Vec<String>>,
+    /// Fewest and most arguments a `fn` definition takes, no most with a `...rest` parameter.
+    pub arity: Option<(usize, Option<usize>)>,
+    /// The `fn` definition it's inside of.
+    pub parent: Option<usize>,
+    /// Defined in the global scope, where it stays around for importers and the REPL.
+    pub global: bool,
+    /// Made by assigning a name nothing defined, which `Storage::set` makes a global.
+    pub implicit: bool,
+    /// The symbol of an outer scope it hides.
+    pub shadows: Option<usize>,
+}
+
+/// A use of a symbol, by index into `Analysis::symbols`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub range: Range<usize>,
+    pub symbol: usize,
+    /// Assigned with `=`, `+=` or `-=` rather than read.
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>,
+}
+
+impl Analysis {
+    /// Index of the symbol defined or used at `offset`.
+    pub fn symbol_at(&self, offset: usize) -> Option<usize> {
+        let contains = |range: &Range<usize>| range.start <= offset && offset <= range.end;
+        self.symbols
+            .iter()
+            .position(|symbol| contains(&symbol.range))
+            .or_else(|| {
+                self.references
+                    .iter()
+                    .find(|reference| contains(&reference.range))
+                    .map(|reference| reference.symbol)
+            })
+    }
+}
+
+/// Parses `input` and resolves every name in it, `builtins` are the names the host defines.
+/// A script that doesn't parse only gets the parse error.
+pub fn analyze(input: &str, builtins: &HashSet<String>) -> Analysis {
+    let pairs = match parse(input) {
+        Ok(pairs) => pairs,
+        Err(e) => {
+            let range = match e.location {
+                InputLocation::Pos(pos) => pos..pos,
+                InputLocation::Span((start, end)) => start..end,
+            };
+            return Analysis {
+                diagnostics: vec![Diagnostic {
+                    range,
+                    severity: Severity::Error,
+                    rule: "syntax",
+                    message: e.variant.message().to_string(),
+                }],
+                ..Default::default()
+            };
+        }
+    };
+
+    let mut walker = Walker {
+        analysis: Analysis::default(),
+        builtins,
+        scopes: vec![HashMap::new()],
+        hoisted: HashMap::new(),
+        parent: None,
+        functions: 0,
+        pending: vec![],
+        strict: false,
+    };
+    let (tests, statements): (Vec<_>, Vec<_>) = pairs
+        .filter(|pair| matches!(pair.as_rule(), Rule::statement | Rule::test_definition))
+        .partition(|pair| pair.as_rule() == Rule::test_definition);
+    walker.strict = statements.first().is_some_and(|statement| {
+        matches!(statement.as_str().trim(), "\"use strict\"" | "'use strict'")
+    });
+    walker.body(statements);
+    // tests run after the top level, each one sees everything it defined
+    for test in tests {
+        walker.walk(test);
+    }
+    walker.finish()
+}
+
+/// Fewest and most arguments taken by `params`, no most with a `...rest` parameter.
+fn arity(params: &[Pair<Rule>]) -> (usize, Option<usize>) {
+    let is_rest = |param: &Pair<Rule>| {
+        param.clone().into_inner().next().unwrap().as_rule() == Rule::rest_pattern
+    };
+    // the ones with a default have a second part
+    let required = params
+        .iter()
+        .filter(|&param| !is_rest(param) && param.clone().into_inner().count() == 1)
+        .count();
+    match params.last() {
+        Some(param) if is_rest(param) => (required, None),
+        _ => (required, Some(params.len())),
+    }
+}
+
+/// Types a `number n` pattern can name, besides `any`.
+const TYPE_NAMES: &[&str] = &[
+    "number", "string", "boolean", "array", "table", "set", "range", "closure", "userdata",
+];
+
+struct Walker<'a> {
+    analysis: Analysis,
+    builtins: &'a HashSet<String>,
+    /// Names visible so far in each scope, innermost last.
+    scopes: Vec<HashMap<String, usize>>,
+    /// Symbols of `fn` definitions by where they start, they're defined before their body runs.
+    hoisted: HashMap<usize, usize>,
+    /// The `fn` definition being walked.
+    parent: Option<usize>,
+    /// How many functions and closures the walk is inside of.
+    functions: usize,
+    /// Names read inside functions that weren't defined yet. Scoping is dynamic, so these can
+    /// be globals defined further down, checked once the walk is over.
+    pending: Vec<(String, Range<usize>, Option<usize>)>,
+    /// Set by a `"use strict"` first statement, undefined names and wrong argument counts only
+    /// fail then.
+    strict: bool,
+}
+
+impl Walker<'_> {
+    fn finish(mut self) -> Analysis {
+        for (name, range, arguments) in std::mem::take(&mut self.pending) {
+            match self.scopes[0].get(&name) {
+                Some(&symbol) => self.reference(symbol, range, arguments),
+                None => self.undefined(&name, range),
+            }
+        }
+        self.analysis
+    }
+
+    fn define(
+        &mut self,
+        name: &Pair<Rule>,
+        kind: SymbolKind,
+        full_range: Range<usize>,
+        value_kind: Option<&'static str>,
+    ) -> usize {
+        self.define_as(name.as_str(), range(name), kind, full_range, value_kind)
+    }
+
+    fn define_as(
+        &mut self,
+        name: &str,
+        range: Range<usize>,
+        kind: SymbolKind,
+        full_range: Range<usize>,
+        value_kind: Option<&'static str>,
+    ) -> usize {
+        let outer = &self.scopes[..self.scopes.len() - 1];
+        let shadows = outer
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied());
+        self.analysis.symbols.push(Symbol {
+            name: name.to_string(),
+            kind,
+            range,
+            full_range,
+            value_kind,
+            params: None,
+            arity: None,
+            parent: self.parent,
+            global: self.scopes.len() == 1,
+            implicit: false,
+            shadows,
+        });
+        let symbol = self.analysis.symbols.len() - 1;
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), symbol);
+        symbol
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    /// Records a read of `name`, with the number of arguments when it's called.
+    fn read(&mut self, name: &Pair<Rule>, arguments: Option<usize>) {
+        let range = range(name);
+        let name = name.as_str();
+        if name == "_" || (self.builtins.contains(name) && self.lookup(name).is_none()) {
+            return;
+        }
+        match self.lookup(name) {
+            Some(symbol) => self.reference(symbol, range, arguments),
+            None if self.functions > 0 => {
+                self.pending.push((name.to_string(), range, arguments));
+            }
+            None => self.undefined(name, range),
+        }
+    }
+
+    /// Severity of a problem that only fails under `"use strict"`, without it an undefined
+    /// name is nil, or defined by a caller.
+    fn severity(&self) -> Severity {
+        if self.strict {
+            Severity::Error
+        } else {
+            Severity::Warning
+        }
+    }
+
+    fn reference(&mut self, symbol: usize, range: Range<usize>, arguments: Option<usize>) {
+        let definition = &self.analysis.symbols[symbol];
+        if let (Some((min, max)), Some(arguments)) = (definition.arity, arguments) {
+            if arguments < min || max.is_some_and(|max| arguments > max) {
+                let message = format!(
+                    "{} expects {} arguments, found {}",
+                    definition.name,
+                    if arguments < min { min } else { max.unwrap() },
+                    arguments
+                );
+                self.analysis.diagnostics.push(Diagnostic {
+                    range: range.clone(),
+                    severity: self.severity(),
+                    rule: "arity",
+                    message,
+                });
+            }
+        }
+        self.analysis.references.push(Reference {
+            range,
+            symbol,
+            write: false,
+        });
+    }
+
+    fn undefined(&mut self, name: &str, range: Range<usize>) {
+        self.analysis.diagnostics.push(Diagnostic {
+            range,
+            severity: self.severity(),
+            rule: "undefined",
+            message: format!("{} is not defined", name),
+        });
+    }
+
+    /// Statements of the program or a block, their `fn` definitions are visible from the start.
+    fn body(&mut self, statements: Vec<Pair<Rule>>) {
+        for statement in &statements {
+            let inner = statement.clone().into_inner().next().unwrap();
+            if inner.as_rule() == Rule::function_definition {
+                self.hoist(&inner);
+            }
+        }
+        for statement in statements {
+            self.walk(statement);
+        }
+    }
+
+    fn hoist(&mut self, function: &Pair<Rule>) -> usize {
+        let mut inner = function.clone().into_inner();
+        let name = inner.next().unwrap();
+        let params = inner.next().unwrap().into_inner().collect::<Vec<_>>();
+        let span = function.as_span();
+        let symbol = self.define(
+            &name,
+            SymbolKind::Function,
+            span.start()..span.end(),
+            Some("closure"),
+        );
+        let definition = &mut self.analysis.symbols[symbol];
+        definition.params = Some(
+            params
+                .iter()
+                .map(|param| param.as_str().to_string())
+                .collect(),
+        );
+        definition.arity = Some(arity(&params));
+        self.hoisted.insert(span.start(), symbol);
+        symbol
+    }
+
+    /// Defines the parameters in the current scope, each default sees the ones before it.
+    fn parameters(&mut self, params: Pair<Rule>) {
+        for param in params.into_inner() {
+            let mut inner = param.into_inner();
+            let pattern = inner.next().unwrap();
+            if let Some(default) = inner.next() {
+                self.walk(default);
+            }
+            self.pattern(pattern, SymbolKind::Parameter);
+        }
+    }
+
+    fn scoped(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn walk(&mut self, pair: Pair<Rule>) {
+        let full_range = range(&pair);
+        match pair.as_rule() {
+            Rule::block => {
+                let statements = pair.into_inner().collect();
+                self.scoped(|walker| walker.body(statements));
+            }
+            Rule::while_statement | Rule::for_statement | Rule::if_expr => {
+                self.scoped(|walker| pair.into_inner().for_each(|pair| walker.walk(pair)));
+            }
+            Rule::function_definition => {
+                // only the direct statements of a body are hoisted
+                let symbol = match self.hoisted.get(&full_range.start) {
+                    Some(&symbol) => symbol,
+                    None => self.hoist(&pair),
+                };
+                let mut inner = pair.into_inner();
+                inner.next();
+                let params = inner.next().unwrap();
+                let body = inner.next().unwrap();
+                let parent = self.parent.replace(symbol);
+                self.functions += 1;
+                self.scoped(|walker| {
+                    walker.parameters(params);
+                    walker.walk(body);
+                });
+                self.functions -= 1;
+                self.parent = parent;
+            }
+            Rule::closure => {
+                let mut inner = pair.into_inner();
+                let params = inner.next().unwrap();
+                let body = inner.next().unwrap();
+                self.functions += 1;
+                self.scoped(|walker| {
+                    walker.parameters(params);
+                    walker.walk(body);
+                });
+                self.functions -= 1;
+            }
+            Rule::import_statement => {
+                let mut inner = pair.into_inner();
+                let path = inner.next().unwrap();
+                match inner.next() {
+                    Some(name) => {
+                        self.define(&name, SymbolKind::Module, full_range, Some("table"));
+                    }
+                    // named after the file when there's no `as`
+                    None => {
+                        let stem = Path::new(&path.as_str()[1..path.as_str().len() - 1])
+                            .file_stem()
+                            .map(|stem| stem.to_string_lossy().to_string())
+                            .unwrap_or_default();
+                        let range = range(&path);
+                        self.define_as(&stem, range, SymbolKind::Module, full_range, Some("table"));
+                    }
+                }
+            }
+            Rule::define_statement => {
+                let name = pair.into_inner().next().unwrap();
+                self.define(&name, SymbolKind::Variable, full_range, Some("nil"));
+            }
+            Rule::define_and_set_statement => {
+                let mut inner = pair.into_inner();
+                let name = inner.next().unwrap();
+                let expression = inner.next().unwrap();
+                let kind = self.kind_of(&expression);
+                self.walk(expression);
+                self.define(&name, SymbolKind::Variable, full_range, kind);
+            }
+            Rule::expression => self.operation(pair.into_inner().collect()),
+            Rule::reference_statement => {
+                let mut inner = pair.into_inner();
+                let name = inner.next().unwrap();
+                let target = inner.next().unwrap();
+                // `->` defines its target when it doesn't exist
+                let kind = match self.lookup(target.as_str()) {
+                    Some(symbol) => {
+                        self.analysis.references.push(Reference {
+                            range: range(&target),
+                            symbol,
+                            write: false,
+                        });
+                        self.analysis.symbols[symbol].value_kind
+                    }
+                    None => {
+                        self.define(&target, SymbolKind::Variable, range(&target), Some("nil"));
+                        Some("nil")
+                    }
+                };
+                self.define(&name, SymbolKind::Variable, full_range, kind);
+            }
+            Rule::identifier => self.read(&pair, None),
+            Rule::function_call => {
+                let mut inner = pair.into_inner();
+                let name = inner.next().unwrap();
+                let arguments = inner.collect::<Vec<_>>();
+                self.read(&name, Some(arguments.len()));
+                arguments.into_iter().for_each(|pair| self.walk(pair));
+            }
+            Rule::table_pair => {
+                // keys are names of entries, not variables, unlike `{x, y}` shorthands
+                pair.into_inner()
+                    .filter(|pair| pair.as_rule() != Rule::identifier)
+                    .for_each(|pair| self.walk(pair));
+            }
+            Rule::map_arm => {
+                self.scoped(|walker| pair.into_inner().for_each(|pair| walker.walk(pair)));
+            }
+            Rule::pattern => match pair.clone().into_inner().next().unwrap().as_rule() {
+                Rule::expression => pair.into_inner().for_each(|pair| self.walk(pair)),
+                _ => self.pattern(pair, SymbolKind::Variable),
+            },
+            Rule::destructure_statement => {
+                let mut inner = pair.into_inner();
+                let pattern = inner.next().unwrap();
+                self.walk(inner.next().unwrap());
+                self.pattern(pattern, SymbolKind::Variable);
+            }
+            Rule::dot_index | Rule::optional_dot_index => (),
+            _ => pair.into_inner().for_each(|pair| self.walk(pair)),
+        }
+    }
+
+    /// Defines the names bound by a pattern of a `map` arm, `:=` or a parameter as `kind`.
+    fn pattern(&mut self, pair: Pair<Rule>, kind: SymbolKind) {
+        match pair.as_rule() {
+            Rule::identifier if pair.as_str() != "_" => {
+                self.define(&pair, kind, range(&pair), None);
+            }
+            Rule::type_pattern => {
+                let mut inner = pair.into_inner();
+                let value_kind = match inner.next().unwrap().as_str() {
+                    "any" => None,
+                    name => TYPE_NAMES.iter().find(|known| **known == name).copied(),
+                };
+                let name = inner.next().unwrap();
+                if name.as_str() != "_" {
+                    self.define(&name, kind, range(&name), value_kind);
+                }
+            }
+            Rule::rest_pattern => {
+                let name = pair.into_inner().next().unwrap();
+                self.define(&name, kind, range(&name), Some("array"));
+            }
+            // keys are names of entries, a key alone also binds the entry to that name
+            Rule::field_pattern => {
+                let mut inner = pair.into_inner();
+                let key = inner.next().unwrap();
+                self.pattern(inner.next().unwrap_or(key), kind);
+            }
+            Rule::regex => {
+                let str = pair.as_str();
+                let Ok(regex) = regex::Regex::new(&str[1..str.len() - 1].replace("\\/", "/"))
+                else {
+                    return;
+                };
+                for name in regex.capture_names().flatten() {
+                    self.define_as(name, range(&pair), kind, range(&pair), None);
+                }
+            }
+            Rule::value_pattern | Rule::string => (),
+            _ => pair.into_inner().for_each(|pair| self.pattern(pair, kind)),
+        }
+    }
+
+    /// Parts of an expression, split at the first assignment as they bind loosest.
+    fn operation(&mut self, mut parts: Vec<Pair<Rule>>) {
+        let Some(i) = parts.iter().position(|part| is_assignment(part.as_rule())) else {
+            parts.into_iter().for_each(|part| self.walk(part));
+            return;
+        };
+        let value = parts.split_off(i + 1);
+        let op = parts.pop().unwrap();
+        let full_range = parts[0].as_span().start()..value.last().unwrap().as_span().end();
+        let kind = self.kind_of_parts(&value);
+        self.operation(value);
+        // changing an element reads the variable it's in
+        if parts.len() > 1 {
+            parts.into_iter().for_each(|part| self.walk(part));
+            return;
+        }
+        let name = parts.pop().unwrap();
+        if op.as_rule() == Rule::define {
+            self.define(&name, SymbolKind::Variable, full_range, kind);
+            return;
+        }
+        match self.lookup(name.as_str()) {
+            Some(symbol) => {
+                self.analysis.references.push(Reference {
+                    range: range(&name),
+                    symbol,
+                    write: true,
+                });
+            }
+            // assigning an unknown name makes a global
+            None => {
+                let kind = match op.as_rule() {
+                    Rule::set => kind,
+                    _ => None,
+                };
+                let symbol = self.define(&name, SymbolKind::Variable, full_range, kind);
+                self.scopes.last_mut().unwrap().remove(name.as_str());
+                self.scopes[0].insert(name.as_str().to_string(), symbol);
+                let symbol = &mut self.analysis.symbols[symbol];
+                symbol.global = true;
+                symbol.implicit = true;
+                symbol.shadows = None;
+            }
+        }
+    }
+
+    /// The `type_name` of what `expression` evaluates to, when it can be told from its shape.
+    fn kind_of(&self, expression: &Pair<Rule>) -> Option<&'static str> {
+        self.kind_of_parts(&expression.clone().into_inner().collect::<Vec<_>>())
+    }
+
+    fn kind_of_parts(&self, parts: &[Pair<Rule>]) -> Option<&'static str> {
+        // the value of an assignment is the one assigned last
+        let parts = match parts.iter().rposition(|part| is_assignment(part.as_rule())) {
+            Some(i) => &parts[i + 1..],
+            None => parts,
+        };
+        let infixes = parts
+            .iter()
+            .map(|part| part.as_rule())
+            .filter(|rule| is_infix(*rule))
+            .collect::<Vec<_>>();
+        if infixes.is_empty() {
+            return self.operand_kind(parts);
+        }
+        // either side can be the value
+        if infixes.contains(&Rule::coalesce) {
+            return None;
+        }
+        let comparison = [
+            Rule::eq,
+            Rule::neq,
+            Rule::lt,
+            Rule::gt,
+            Rule::lte,
+            Rule::gte,
+            Rule::and,
+            Rule::or,
+            Rule::is_in,
+        ];
+        if infixes.iter().any(|rule| comparison.contains(rule)) {
+            return Some("boolean");
+        }
+        if infixes
+            .iter()
+            .any(|rule| matches!(rule, Rule::exclusive_range | Rule::inclusive_range))
+        {
+            return Some("range");
+        }
+        let kinds = parts
+            .split(|part| is_infix(part.as_rule()))
+            .map(|operand| self.operand_kind(operand))
+            .collect::<Vec<_>>();
+        if kinds.iter().all(|kind| *kind == Some("number")) {
+            Some("number")
+        } else if infixes == [Rule::add] && kinds.contains(&Some("string")) {
+            Some("string")
+        } else {
+            None
+        }
+    }
+
+    /// Kind of a primary with its prefixes and postfixes.
+    fn operand_kind(&self, parts: &[Pair<Rule>]) -> Option<&'static str> {
+        let mut kind = None;
+        for part in parts {
+            kind = match part.as_rule() {
+                Rule::not => return Some("boolean"),
+                Rule::negate => return Some("number"),
+                Rule::hash | Rule::double_hash | Rule::triple_hash => return None,
+                Rule::index | Rule::dot_index | Rule::optional_dot_index | Rule::call => {
+                    return None
+                }
+                Rule::debug | Rule::print => kind,
+                Rule::value => match part.clone().into_inner().next().unwrap().as_rule() {
+                    Rule::number => Some("number"),
+                    Rule::string => Some("string"),
+                    Rule::boolean => Some("boolean"),
+                    Rule::nil => Some("nil"),
+                    Rule::closure => Some("closure"),
+                    _ => None,
+                },
+                Rule::array => Some("array"),
+                Rule::table => Some("table"),
+                Rule::set_literal => Some("set"),
+                Rule::identifier => self
+                    .lookup(part.as_str())
+                    .and_then(|symbol| self.analysis.symbols[symbol].value_kind),
+                Rule::expression => self.kind_of(part),
+                _ => None,
+            };
+        }
+        kind
+    }
+}
+
+pub(super) fn range(pair: &Pair<Rule>) -> Range<usize> {
+    pair.as_span().start()..pair.as_span().end()
+}
+
+pub(super) fn is_infix(rule: Rule) -> bool {
+    use Rule::*;
+    matches!(
+        rule,
+        coalesce
+            | and
+            | or
+            | eq
+            | neq
+            | lte
+            | gte
+            | lt
+            | gt
+            | is_in
+            | inclusive_range
+            | exclusive_range
+            | add
+            | sub
+            | mul
+            | div
+            | modulo
+            | at
+            | ampersand
+            | double_dollar
+            | dollar
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analysis(input: &str) -> Analysis {
+        analyze(input, &HashSet::from(["print".to_string()]))
+    }
+
+    fn rules(analysis: &Analysis) -> Vec<(&'static str, Severity)> {
+        analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.rule, diagnostic.severity))
+            .collect()
+    }
+
+    #[test]
+    fn symbols_and_references() {
+        let src = "fn add(a, b = 1) {\n  a + b\n}\ntotal := add(2)\n";
+        let analysis = analysis(src);
+        assert!(
+            analysis.diagnostics.is_empty(),
+            "{:?}",
+            analysis.diagnostics
+        );
+        let add = &analysis.symbols[analysis.symbol_at(src.find("add").unwrap()).unwrap()];
+        assert_eq!(add.kind, SymbolKind::Function);
+        assert_eq!(add.arity, Some((1, Some(2))));
+        assert_eq!(add.params, Some(vec!["a".to_string(), "b = 1".to_string()]));
+        // the call refers back to the definition
+        let call = src.rfind("add").unwrap();
+        assert_eq!(
+            analysis.symbol_at(call),
+            analysis.symbol_at(src.find("add").unwrap())
+        );
+        let total = analysis.symbols.iter().find(|s| s.name == "total").unwrap();
+        assert_eq!((total.kind, total.global), (SymbolKind::Variable, true));
+    }
+
+    #[test]
+    fn undefined_names() {
+        assert_eq!(
+            rules(&analysis("x + 1")),
+            [("undefined", Severity::Warning)]
+        );
+        assert_eq!(
+            rules(&analysis("\"use strict\"\nx + 1")),
+            [("undefined", Severity::Error)]
+        );
+        assert!(analysis("print(1)").diagnostics.is_empty());
+        // functions can read globals defined after them
+        assert!(analysis("fn f() { later }\nlater := 1")
+            .diagnostics
+            .is_empty());
+    }
+
+    #[test]
+    fn arity_is_a_warning_outside_strict_mode() {
+        let src = "fn f(a, b) { a }\nf(1)";
+        assert_eq!(rules(&analysis(src)), [("arity", Severity::Warning)]);
+        let strict = format!("\"use strict\"\n{}", src);
+        assert_eq!(rules(&analysis(&strict)), [("arity", Severity::Error)]);
+        assert!(analysis("fn g(...rest) { rest }\ng(1, 2, 3)")
+            .diagnostics
+            .is_empty());
+    }
+
+    #[test]
+    fn syntax_errors_stop_the_analysis() {
+        let analysis = analysis("x := (");
+        assert_eq!(rules(&analysis), [("syntax", Severity::Error)]);
+        assert!(analysis.symbols.is_empty());
+    }
+}
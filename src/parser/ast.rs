@@ -1,6 +1,12 @@
 pub mod nodes {
     #![allow(unused)]
-    use std::collections::HashMap;
+    use std::{
+        cell::RefCell,
+        cmp::Ordering,
+        collections::{HashMap, HashSet},
+        fmt,
+        rc::{Rc, Weak},
+    };
 
     use serde::{ser::SerializeMap, Serialize, Serializer};
 
@@ -8,22 +14,45 @@ pub mod nodes {
     pub struct Program {
         pub statements: Vec<Statement>,
         pub functions: Vec<Function>,
+        /// Where each statement starts, parallel to `statements`.
+        pub spans: Vec<Span>,
+        /// `test` blocks, only run by `qs test`.
+        pub tests: Vec<Test>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct Test {
+        pub name: String,
+        pub body: Block,
+        /// Where `test` is.
+        pub span: Span,
     }
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub struct Function {
         pub name: String,
         pub closure: NormalClosure,
+        /// Where `fn` is.
+        pub span: Span,
     }
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub enum Statement {
         Expression(Expression),
-        Set(SetStatement),
         Define(DefineStatement),
-        DefineAndSet(DefineAndSetStatement),
         While(WhileStatement),
         For(ForStatement),
+        Import(ImportStatement),
+        Reference(ReferenceStatement),
+        Destructure(DestructureStatement),
+    }
+
+    /// `[a, b] := expression`, defines each name the pattern binds.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct DestructureStatement {
+        pub pattern: Pattern,
+        pub expression: Expression,
+        pub span: Span,
     }
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -46,46 +75,93 @@ pub mod nodes {
         pub statement: Box<Statement>,
     }
 
+    /// `target op expression`, assignments bind loosest and group to the right.
     #[derive(Debug, Clone, PartialEq, Serialize)]
-    pub struct SetStatement {
-        pub identifier: String,
+    pub struct AssignExpression {
+        pub target: Target,
         pub op: SetOp,
-        pub expression: Expression,
+        pub expression: Box<Expression>,
+        pub span: Span,
     }
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub enum SetOp {
+        /// `:=`, only ever has a plain name as its target.
+        Define,
         Set,
         Increment,
         Decrement,
     }
 
+    /// What an assignment changes: a variable, or an element or entry inside one.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct Target {
+        pub identifier: String,
+        /// `[index]` and `.key` after the name, outermost first.
+        pub path: Vec<Accessor>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub enum Accessor {
+        Index(Expression),
+        Key(String),
+    }
+
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub struct DefineStatement {
         pub identifier: String,
+        pub span: Span,
+    }
+
+    /// Where the variable assigned by a statement is named.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct Span {
+        pub source: String,
+        pub line: usize,
+        pub column: usize,
     }
 
+    /// `identifier -> target`, both names share one value from then on.
     #[derive(Debug, Clone, PartialEq, Serialize)]
-    pub struct DefineAndSetStatement {
+    pub struct ReferenceStatement {
         pub identifier: String,
-        pub expression: Expression,
+        pub target: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct ImportStatement {
+        pub path: String,
+        /// Set by `as`, defaults to the file name without its extension.
+        pub name: Option<String>,
+        /// File containing the import, relative paths are resolved from its directory.
+        pub source: String,
     }
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub enum Expression {
-        Value(Value),
+        Value(#[serde(serialize_with = "serialize_literal")] Value),
         Infixed(Operation),
         Prefixed(PrefixedExpression),
         Postfixed(PostfixedExpression),
+        Assign(AssignExpression),
         Identifier(String),
         Block(Block),
         Map(MapExpression),
         FunctionCall(FunctionCall),
         Array(ExpressionsArray),
         Table(ExpressionsTable),
+        Set(ExpressionsArray),
         If(IfExpression),
     }
 
+    /// Closure literals are dumped as their syntax tree, which `Value` doesn't serialize.
+    fn serialize_literal<S: Serializer>(value: &Value, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Value::Closure(closure) => closure.serialize(serializer),
+            value => value.serialize(serializer),
+        }
+    }
+
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub struct ExpressionsArray {
         pub elements: Vec<Expression>,
@@ -93,7 +169,16 @@ pub mod nodes {
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub struct ExpressionsTable {
-        pub map: HashMap<String, Expression>,
+        /// In source order, a later entry replaces an earlier one with the same key.
+        pub entries: Vec<(TableKey, Expression)>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub enum TableKey {
+        /// `name: value`, `"any text": value` and the shorthand `name`.
+        Fixed(String),
+        /// `[expression]: value`, the expression should give a string.
+        Computed(Expression),
     }
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -105,22 +190,59 @@ pub mod nodes {
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub struct MapExpression {
         pub input: Box<Expression>,
-        pub map: Vec<(Vec<Expression>, Expression)>,
+        /// Tried in order, the first one matching gives the value.
+        pub arms: Vec<MapArm>,
+    }
+
+    /// `pattern | pattern if guard => value`, the names the patterns bind are only visible
+    /// in the guard and the value.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct MapArm {
+        pub patterns: Vec<Pattern>,
+        pub guard: Option<Expression>,
+        pub value: Expression,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub enum Pattern {
+        /// `_`, matches anything.
+        Wildcard,
+        /// A name inside a pattern, matches anything and binds it.
+        Binding(String),
+        /// Compares with the value, a range also matches the numbers inside it.
+        Value(Expression),
+        /// `number n`, matches values of that type, `any` matches them all.
+        Type(String, String),
+        /// `[a, b, ...rest]`, the lengths have to match unless there's a rest.
+        Array(Vec<Pattern>),
+        /// `...rest` in an array pattern, binds an array of the elements no other pattern took.
+        Rest(String),
+        /// `{x, y: pattern}`, the table has to have every key, and can have others.
+        Table(Vec<(String, Pattern)>),
+        /// `/regex/`, matches strings it finds a match in, binding its named groups.
+        Regex(String),
     }
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub struct Block {
         pub statements: Vec<Statement>,
         pub functions: Vec<Function>,
+        /// Where each statement starts, parallel to `statements`.
+        pub spans: Vec<Span>,
+        /// Where the closing brace is.
+        pub end: Span,
     }
 
-    #[derive(Debug, Clone, PartialEq, Serialize)]
+    /// Serializes as plain data: numbers, strings, arrays and maps.
+    #[derive(Debug, Clone, PartialEq)]
     pub enum Value {
         Table(ValuesTable),
         ExclusiveRange(f64, f64),
         InclusiveRange(f64, f64),
         Array(ValuesArray),
+        Set(ValuesSet),
         Closure(Closure),
+        UserData(UserData),
         Number(f64),
         String(String),
         Boolean(bool),
@@ -132,6 +254,91 @@ pub mod nodes {
         pub map: HashMap<String, Value>,
     }
 
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct ValuesSet {
+        pub elements: HashSet<Key>,
+    }
+
+    impl ValuesSet {
+        /// Elements in a stable order, for printing and iterating.
+        pub fn sorted(&self) -> Vec<&Key> {
+            let mut elements = self.elements.iter().collect::<Vec<_>>();
+            elements.sort();
+            elements
+        }
+    }
+
+    /// Hashable form of the values a set can hold.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum Key {
+        Nil,
+        Boolean(bool),
+        /// Bits of the number, with `-0` folded into `0` and every nan into one.
+        Number(u64),
+        String(String),
+        Array(Vec<Key>),
+    }
+
+    impl Key {
+        /// `None` for tables, ranges, closures and userdata.
+        pub fn new(value: &Value) -> Option<Key> {
+            Some(match value {
+                Value::Nil => Key::Nil,
+                Value::Boolean(b) => Key::Boolean(*b),
+                Value::Number(n) if n.is_nan() => Key::Number(f64::NAN.to_bits()),
+                Value::Number(n) => Key::Number((n + 0.).to_bits()),
+                Value::String(s) => Key::String(s.clone()),
+                Value::Array(array) => {
+                    Key::Array(array.elements.iter().map(Key::new).collect::<Option<_>>()?)
+                }
+                _ => return None,
+            })
+        }
+
+        pub fn to_value(&self) -> Value {
+            match self {
+                Key::Nil => Value::Nil,
+                Key::Boolean(b) => Value::Boolean(*b),
+                Key::Number(bits) => Value::Number(f64::from_bits(*bits)),
+                Key::String(s) => Value::String(s.clone()),
+                Key::Array(keys) => Value::Array(ValuesArray {
+                    elements: keys.iter().map(Key::to_value).collect(),
+                }),
+            }
+        }
+
+        fn rank(&self) -> u8 {
+            match self {
+                Key::Nil => 0,
+                Key::Boolean(_) => 1,
+                Key::Number(_) => 2,
+                Key::String(_) => 3,
+                Key::Array(_) => 4,
+            }
+        }
+    }
+
+    /// nil, then booleans, numbers, strings and arrays.
+    impl Ord for Key {
+        fn cmp(&self, other: &Self) -> Ordering {
+            match (self, other) {
+                (Key::Boolean(a), Key::Boolean(b)) => a.cmp(b),
+                (Key::Number(a), Key::Number(b)) => {
+                    f64::from_bits(*a).total_cmp(&f64::from_bits(*b))
+                }
+                (Key::String(a), Key::String(b)) => a.cmp(b),
+                (Key::Array(a), Key::Array(b)) => a.cmp(b),
+                _ => self.rank().cmp(&other.rank()),
+            }
+        }
+    }
+
+    impl PartialOrd for Key {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub struct ValuesArray {
         pub elements: Vec<Value>,
@@ -145,13 +352,76 @@ pub mod nodes {
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub struct NormalClosure {
-        pub arguments: Vec<String>,
+        pub arguments: Vec<Parameter>,
         pub body: Box<Expression>,
+        /// Globals of the module the closure was imported from.
+        #[serde(skip)]
+        pub env: Option<Env>,
     }
 
-    #[derive(Debug, Clone, PartialEq)]
+    /// A name, `...rest` or a pattern the argument is destructured with.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct Parameter {
+        pub pattern: Pattern,
+        /// Evaluated when the argument is missing, after the parameters before it are bound.
+        pub default: Option<Expression>,
+    }
+
+    impl NormalClosure {
+        /// Fewest and most arguments it takes, no most with a `...rest` parameter.
+        pub fn arity(&self) -> (usize, Option<usize>) {
+            let required = self
+                .arguments
+                .iter()
+                .filter(|p| p.default.is_none() && !matches!(p.pattern, Pattern::Rest(_)))
+                .count();
+            match self.arguments.last() {
+                Some(Parameter {
+                    pattern: Pattern::Rest(_),
+                    ..
+                }) => (required, None),
+                _ => (required, Some(self.arguments.len())),
+            }
+        }
+    }
+
+    pub type Globals = Rc<RefCell<HashMap<String, Value>>>;
+
+    /// Weak so modules whose functions refer to each other can still be freed.
+    #[derive(Clone)]
+    pub struct Env {
+        pub globals: Weak<RefCell<HashMap<String, Value>>>,
+    }
+
+    impl fmt::Debug for Env {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("Env")
+        }
+    }
+
+    impl PartialEq for Env {
+        fn eq(&self, other: &Self) -> bool {
+            self.globals.ptr_eq(&other.globals)
+        }
+    }
+
+    pub type NativeFunction = dyn Fn(Vec<Value>) -> crate::runtime::Result<Value>;
+
+    #[derive(Clone)]
     pub struct NativeClosure {
-        pub function: fn(Vec<Value>) -> Value,
+        pub function: Rc<NativeFunction>,
+    }
+
+    impl fmt::Debug for NativeClosure {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("NativeClosure")
+        }
+    }
+
+    impl PartialEq for NativeClosure {
+        fn eq(&self, other: &Self) -> bool {
+            Rc::ptr_eq(&self.function, &other.function)
+        }
     }
 
     impl Serialize for NativeClosure {
@@ -163,6 +433,33 @@ pub mod nodes {
         }
     }
 
+    /// A host object handed to scripts, see `runtime::HostType`.
+    #[derive(Clone)]
+    pub struct UserData {
+        pub object: Rc<dyn crate::runtime::HostObject>,
+    }
+
+    impl fmt::Debug for UserData {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(&self.object.label())
+        }
+    }
+
+    impl PartialEq for UserData {
+        fn eq(&self, other: &Self) -> bool {
+            Rc::ptr_eq(&self.object, &other.object)
+        }
+    }
+
+    impl Serialize for UserData {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.serialize_str(&self.object.label())
+        }
+    }
+
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub enum PrefixedExpression {
         Negative(Box<Expression>),
@@ -174,10 +471,25 @@ pub mod nodes {
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
     pub enum PostfixedExpression {
-        Debug(Box<Expression>),
+        Debug(Box<Expression>, DebugInfo),
         Print(Box<Expression>),
         Index(Box<Expression>, Box<Expression>),
         DotIndex(Box<Expression>, String),
+        /// `?.key`, nil instead of indexing nil.
+        OptionalDotIndex(Box<Expression>, String),
+        Call(Box<Expression>, Vec<Expression>),
+    }
+
+    /// Where a `??` appeared, so it can say what it's printing.
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    pub struct DebugInfo {
+        /// One less than the number of `?`s.
+        pub level: usize,
+        pub source: String,
+        pub line: usize,
+        pub column: usize,
+        /// The debugged expression as written.
+        pub text: String,
     }
 
     #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -201,16 +513,20 @@ pub mod nodes {
         Lte,
         And,
         Or,
+        /// `?:`, the right side when the left one is nil.
+        Coalesce,
         Dollar,
         DoubleDollar,
         ExclusiveRange,
         InclusiveRange,
         Modulo,
         At,
+        Ampersand,
+        In,
     }
 }
 
-use std::collections::HashMap;
+use std::cell::RefCell;
 
 use nodes::*;
 
@@ -244,6 +560,29 @@ impl<'a> BetterPair<'a> for Pair<'a> {
     }
 }
 
+thread_local! {
+    /// Name of the source being parsed, for `DebugInfo`.
+    static SOURCE: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+pub fn set_source_name(name: &str) {
+    SOURCE.with(|source| *source.borrow_mut() = name.to_string());
+}
+
+/// `DebugInfo` of the `??` in `op`, after the operand starting at `start`.
+fn debug_info(op: &Pair, start: usize) -> DebugInfo {
+    let end = op.as_span().start();
+    let position = ::pest::Position::new(op.get_input(), start).unwrap();
+    let (line, column) = position.line_col();
+    DebugInfo {
+        level: op.as_str().len() - 1,
+        source: SOURCE.with(|source| source.borrow().clone()),
+        line,
+        column,
+        text: op.get_input()[start..end].trim_end().to_string(),
+    }
+}
+
 pub trait ParseMulti {
     fn parse(pairs: Pairs) -> Self;
 }
@@ -256,15 +595,21 @@ impl ParseMulti for Program {
     fn parse(pairs: Pairs) -> Self {
         let mut statements = vec![];
         let mut functions = vec![];
+        let mut spans = vec![];
+        let mut tests = vec![];
         for pair in pairs {
             match pair.as_rule() {
                 Rule::statement => {
                     let pair = pair.first_child();
                     match pair.as_rule() {
-                        Rule::function_definition => functions.push(Function::parse(pair.childs())),
-                        _ => statements.push(Statement::parse(pair)),
+                        Rule::function_definition => functions.push(Function::parse(pair)),
+                        _ => {
+                            spans.push(Span::parse(pair.clone()));
+                            statements.push(Statement::parse(pair));
+                        }
                     }
                 }
+                Rule::test_definition => tests.push(Test::parse(pair)),
 
                 Rule::EOI => (),
                 _ => unreachable!("{:#?}", pair),
@@ -273,40 +618,66 @@ impl ParseMulti for Program {
         Program {
             statements,
             functions,
+            spans,
+            tests,
         }
     }
 }
 
-impl ParseMulti for Function {
-    fn parse(mut pairs: Pairs) -> Self {
+impl ParseSingle for Test {
+    fn parse(pair: Pair) -> Self {
+        let span = Span::parse(pair.clone());
+        let mut pairs = pair.childs();
+        let name = pairs.take_().as_str();
+        Test {
+            name: name[1..name.len() - 1].to_string(),
+            body: Block::parse(pairs.take_()),
+            span,
+        }
+    }
+}
+
+impl ParseSingle for Function {
+    fn parse(pair: Pair) -> Self {
+        let span = Span::parse(pair.clone());
+        let mut pairs = pair.childs();
         let name = pairs.take_().as_str().to_string();
-        let arguments = pairs
-            .take_()
-            .childs()
-            .map(|p| p.as_str().to_string())
-            .collect();
+        let arguments = pairs.take_().childs().map(Parameter::parse).collect();
         let body = Expression::parse(pairs.take_().childs());
         Function {
             name,
             closure: NormalClosure {
                 arguments,
                 body: Box::new(body),
+                env: None,
             },
+            span,
         }
     }
 }
 
-impl ParseMulti for Block {
-    fn parse(pairs: Pairs) -> Self {
+impl ParseSingle for Block {
+    fn parse(pair: Pair) -> Self {
+        let (line, column) = pair.as_span().end_pos().line_col();
+        let end = Span {
+            source: SOURCE.with(|source| source.borrow().clone()),
+            line,
+            column,
+        };
+        let pairs = pair.childs();
         let mut statements = vec![];
         let mut functions = vec![];
+        let mut spans = vec![];
         for pair in pairs {
             match pair.as_rule() {
                 Rule::statement => {
                     let pair = pair.first_child();
                     match pair.as_rule() {
-                        Rule::function_definition => functions.push(Function::parse(pair.childs())),
-                        _ => statements.push(Statement::parse(pair)),
+                        Rule::function_definition => functions.push(Function::parse(pair)),
+                        _ => {
+                            spans.push(Span::parse(pair.clone()));
+                            statements.push(Statement::parse(pair));
+                        }
                     }
                 }
                 _ => unreachable!("{:#?}", pair),
@@ -315,6 +686,8 @@ impl ParseMulti for Block {
         Block {
             statements,
             functions,
+            spans,
+            end,
         }
     }
 }
@@ -323,18 +696,61 @@ impl ParseSingle for Statement {
     fn parse(pair: Pair) -> Self {
         match pair.as_rule() {
             Rule::expression => Statement::Expression(Expression::parse(pair.childs())),
-            Rule::set_statement => Statement::Set(SetStatement::parse(pair.childs())),
             Rule::define_statement => Statement::Define(DefineStatement::parse(pair.first_child())),
+            // `let x = 1` is `x := 1`
             Rule::define_and_set_statement => {
-                Statement::DefineAndSet(DefineAndSetStatement::parse(pair.childs()))
+                let mut pairs = pair.childs();
+                let identifier = pairs.take_();
+                Statement::Expression(Expression::Assign(AssignExpression {
+                    span: Span::parse(identifier.clone()),
+                    target: Target {
+                        identifier: identifier.as_str().to_string(),
+                        path: vec![],
+                    },
+                    op: SetOp::Define,
+                    expression: Box::new(Expression::parse(pairs.take_().childs())),
+                }))
             }
             Rule::while_statement => Statement::While(WhileStatement::parse(pair.childs())),
             Rule::for_statement => Statement::For(ForStatement::parse(pair.childs())),
+            Rule::import_statement => Statement::Import(ImportStatement::parse(pair.childs())),
+            Rule::reference_statement => {
+                Statement::Reference(ReferenceStatement::parse(pair.childs()))
+            }
+            Rule::destructure_statement => {
+                let mut pairs = pair.childs();
+                let pattern = pairs.take_();
+                Statement::Destructure(DestructureStatement {
+                    span: Span::parse(pattern.clone()),
+                    pattern: Pattern::parse(pattern),
+                    expression: Expression::parse(pairs.take_().childs()),
+                })
+            }
             _ => unreachable!("{:#?}", pair),
         }
     }
 }
 
+impl ParseMulti for ReferenceStatement {
+    fn parse(mut pairs: Pairs) -> Self {
+        ReferenceStatement {
+            identifier: pairs.take_().as_str().to_string(),
+            target: pairs.take_().as_str().to_string(),
+        }
+    }
+}
+
+impl ParseMulti for ImportStatement {
+    fn parse(mut pairs: Pairs) -> Self {
+        let path = pairs.take_().as_str();
+        ImportStatement {
+            path: path[1..path.len() - 1].to_string(),
+            name: pairs.next().map(|name| name.as_str().to_string()),
+            source: SOURCE.with(|source| source.borrow().clone()),
+        }
+    }
+}
+
 impl ParseMulti for ForStatement {
     fn parse(mut pairs: Pairs) -> Self {
         let initializer = Statement::parse(pairs.take_().first_child());
@@ -390,81 +806,158 @@ impl ParseMulti for IfExpression {
     }
 }
 
-impl ParseSingle for DefineStatement {
+impl ParseSingle for Span {
     fn parse(pair: Pair) -> Self {
-        let identifier = pair.as_str().to_string();
-        DefineStatement { identifier }
+        let (line, column) = pair.line_col();
+        Span {
+            source: SOURCE.with(|source| source.borrow().clone()),
+            line,
+            column,
+        }
     }
 }
 
-impl ParseMulti for DefineAndSetStatement {
-    fn parse(mut pairs: Pairs) -> Self {
-        let identifier = pairs.take_().as_str().to_string();
-        let expression = pairs.take_();
-        DefineAndSetStatement {
+impl ParseSingle for DefineStatement {
+    fn parse(pair: Pair) -> Self {
+        let identifier = pair.as_str().to_string();
+        DefineStatement {
             identifier,
-            expression: Expression::parse(expression.childs()),
+            span: Span::parse(pair),
         }
     }
 }
 
-impl ParseMulti for SetStatement {
-    fn parse(mut pairs: Pairs) -> Self {
-        let identifier = pairs.take_().as_str().to_string();
-        let op = pairs.take_().first_child().as_rule();
-        let op = match op {
-            Rule::set => SetOp::Set,
-            Rule::increment => SetOp::Increment,
-            Rule::decrement => SetOp::Decrement,
-            _ => unreachable!("{:#?}", op),
-        };
-        let expression = pairs.take_();
-        SetStatement {
-            identifier,
-            op,
-            expression: Expression::parse(expression.childs()),
-        }
+/// The `:=`, `=`, `+=` or `-=` an operator pair is, if it's one of them.
+pub(super) fn set_op(rule: Rule) -> Option<SetOp> {
+    match rule {
+        Rule::define => Some(SetOp::Define),
+        Rule::set => Some(SetOp::Set),
+        Rule::increment => Some(SetOp::Increment),
+        Rule::decrement => Some(SetOp::Decrement),
+        _ => None,
     }
 }
 
-impl ParseMulti for MapExpression {
-    fn parse(pairs: Pairs) -> Self {
-        let mut iter = pairs.into_iter();
-        let mut map = vec![];
-        let input = Expression::parse(iter.take_().childs());
+/// The target of an assignment, a name followed by indexes, checked by `pest::parse`.
+fn parse_target(pairs: Vec<Pair>) -> Target {
+    let mut pairs = pairs.into_iter();
+    Target {
+        identifier: pairs.next().unwrap().as_str().to_string(),
+        path: pairs
+            .map(|pair| match pair.as_rule() {
+                Rule::index => Accessor::Index(Expression::parse(pair.childs())),
+                Rule::dot_index => Accessor::Key(pair.first_child().as_str().to_string()),
+                _ => unreachable!("{:#?}", pair),
+            })
+            .collect(),
+    }
+}
 
-        while let Some(pair) = iter.next() {
-            let mut cases = vec![];
-            for case in pair.childs() {
-                cases.push(Expression::parse(case.childs()));
-            }
-            let value = Expression::parse(iter.take_().childs());
-            map.push((cases, value));
-        }
+impl ParseMulti for MapExpression {
+    fn parse(mut pairs: Pairs) -> Self {
+        let input = Expression::parse(pairs.take_().childs());
+        let arms = pairs.map(|arm| MapArm::parse(arm.childs())).collect();
 
         MapExpression {
             input: Box::new(input),
-            map,
+            arms,
         }
     }
 }
 
-impl ParseMulti for Closure {
+impl ParseMulti for MapArm {
     fn parse(mut pairs: Pairs) -> Self {
-        let args = pairs
+        let patterns = pairs
             .take_()
             .childs()
-            .map(|a| a.as_str().to_string())
+            .map(|pattern| Pattern::parse(pattern.first_child()))
             .collect();
+        let mut pair = pairs.take_();
+        let guard = match pair.as_rule() {
+            Rule::guard => {
+                let guard = Expression::parse(pair.first_child().childs());
+                pair = pairs.take_();
+                Some(guard)
+            }
+            _ => None,
+        };
+
+        MapArm {
+            patterns,
+            guard,
+            value: Expression::parse(pair.childs()),
+        }
+    }
+}
+
+impl ParseSingle for Pattern {
+    fn parse(pair: Pair) -> Self {
+        match pair.as_rule() {
+            Rule::identifier if pair.as_str() == "_" => Pattern::Wildcard,
+            Rule::identifier => Pattern::Binding(pair.as_str().to_string()),
+            // `_` alone is an expression at the top of an arm
+            Rule::expression if pair.as_str().trim() == "_" => Pattern::Wildcard,
+            Rule::expression => Pattern::Value(Expression::parse(pair.childs())),
+            Rule::value_pattern => Pattern::Value(parse_operation(pair.childs().collect())),
+            Rule::type_pattern => {
+                let mut pairs = pair.childs();
+                let name = pairs.take_().as_str().to_string();
+                Pattern::Type(name, pairs.take_().as_str().to_string())
+            }
+            Rule::array_pattern => Pattern::Array(pair.childs().map(Pattern::parse).collect()),
+            Rule::rest_pattern => Pattern::Rest(pair.first_child().as_str().to_string()),
+            Rule::table_pattern => Pattern::Table(
+                pair.childs()
+                    .map(|field| {
+                        let mut pairs = field.childs();
+                        let key = pairs.take_();
+                        let name = match key.as_rule() {
+                            Rule::string => key.as_str()[1..key.as_str().len() - 1].to_string(),
+                            _ => key.as_str().to_string(),
+                        };
+                        // `{x}` is short for `{x: x}`
+                        let pattern = match pairs.next() {
+                            Some(pattern) => Pattern::parse(pattern),
+                            None => Pattern::parse(key),
+                        };
+                        (name, pattern)
+                    })
+                    .collect(),
+            ),
+            Rule::regex => {
+                let str = pair.as_str();
+                Pattern::Regex(str[1..str.len() - 1].replace("\\/", "/"))
+            }
+            _ => unreachable!("{:#?}", pair),
+        }
+    }
+}
+
+impl ParseMulti for Closure {
+    fn parse(mut pairs: Pairs) -> Self {
+        let args = pairs.take_().childs().map(Parameter::parse).collect();
         let body = Expression::parse(pairs.take_().childs());
 
         Closure::Normal(NormalClosure {
             arguments: args,
             body: Box::new(body),
+            env: None,
         })
     }
 }
 
+impl ParseSingle for Parameter {
+    fn parse(pair: Pair) -> Self {
+        let mut pairs = pair.childs();
+        Parameter {
+            pattern: Pattern::parse(pairs.take_()),
+            default: pairs
+                .next()
+                .map(|default| Expression::parse(default.childs())),
+        }
+    }
+}
+
 impl ParseMulti for FunctionCall {
     fn parse(mut pairs: Pairs) -> Self {
         let name = pairs.take_().as_str().to_string();
@@ -485,89 +978,167 @@ impl ParseMulti for ExpressionsArray {
 }
 
 impl ParseMulti for ExpressionsTable {
-    fn parse(mut pairs: Pairs) -> Self {
-        let mut map = HashMap::new();
-
-        while let Some(ident) = pairs.next() {
-            let key = ident.as_str().to_string();
-            let value = Expression::parse(pairs.take_().childs());
-            map.insert(key, value);
-        }
-
-        Self { map }
+    fn parse(pairs: Pairs) -> Self {
+        let entries = pairs
+            .map(|pair| match pair.as_rule() {
+                // shorthand, `{x, y}` is `{x: x, y: y}`
+                Rule::identifier => {
+                    let name = pair.as_str().to_string();
+                    (TableKey::Fixed(name.clone()), Expression::Identifier(name))
+                }
+                Rule::table_pair => {
+                    let mut pairs = pair.childs();
+                    let key = pairs.take_();
+                    let key = match key.as_rule() {
+                        Rule::identifier => TableKey::Fixed(key.as_str().to_string()),
+                        Rule::string => {
+                            let str = key.as_str();
+                            TableKey::Fixed(str[1..str.len() - 1].to_string())
+                        }
+                        Rule::computed_key => {
+                            TableKey::Computed(Expression::parse(key.first_child().childs()))
+                        }
+                        _ => unreachable!("{:#?}", key),
+                    };
+                    (key, Expression::parse(pairs.take_().childs()))
+                }
+                _ => unreachable!("{:#?}", pair),
+            })
+            .collect();
+        Self { entries }
     }
 }
 
 impl ParseMulti for Expression {
     fn parse(pairs: Pairs) -> Self {
-        PRATT_PARSER
-            .map_primary(|primary| match primary.as_rule() {
-                Rule::value => Expression::Value(Value::parse(primary.first_child())),
-                Rule::expression => Expression::parse(primary.childs()),
-                Rule::identifier => Expression::Identifier(primary.as_str().to_string()),
-                Rule::block => Expression::Block(Block::parse(primary.childs())),
-                Rule::map => Expression::Map(MapExpression::parse(primary.childs())),
-                Rule::function_call => {
-                    Expression::FunctionCall(FunctionCall::parse(primary.childs()))
-                }
-                Rule::array => Expression::Array(ExpressionsArray::parse(primary.childs())),
-                Rule::if_expr => Expression::If(IfExpression::parse(primary.childs())),
-                Rule::table => Expression::Table(ExpressionsTable::parse(primary.childs())),
-                _ => unreachable!("{:#?}", primary),
-            })
-            .map_infix(|lhs, op, rhs| {
-                Expression::Infixed(Operation {
-                    lhs: Box::new(lhs),
-                    infix: match op.as_rule() {
-                        Rule::add => Operator::Add,
-                        Rule::sub => Operator::Sub,
-                        Rule::mul => Operator::Mul,
-                        Rule::div => Operator::Div,
-                        Rule::eq => Operator::Eq,
-                        Rule::neq => Operator::Neq,
-                        Rule::gt => Operator::Gt,
-                        Rule::lt => Operator::Lt,
-                        Rule::gte => Operator::Gte,
-                        Rule::lte => Operator::Lte,
-                        Rule::and => Operator::And,
-                        Rule::or => Operator::Or,
-                        Rule::dollar => Operator::Dollar,
-                        Rule::double_dollar => Operator::DoubleDollar,
-                        Rule::exclusive_range => Operator::ExclusiveRange,
-                        Rule::inclusive_range => Operator::InclusiveRange,
-                        Rule::modulo => Operator::Modulo,
-                        Rule::at => Operator::At,
-                        _ => unreachable!("{:#?}", op),
-                    },
-                    rhs: Box::new(rhs),
-                })
-            })
-            .map_prefix(|op, rhs| match op.as_rule() {
-                Rule::negate => Expression::Prefixed(PrefixedExpression::Negative(Box::new(rhs))),
-                Rule::not => Expression::Prefixed(PrefixedExpression::Not(Box::new(rhs))),
-                Rule::hash => Expression::Prefixed(PrefixedExpression::Hash(Box::new(rhs))),
-                Rule::double_hash => {
-                    Expression::Prefixed(PrefixedExpression::DoubleHash(Box::new(rhs)))
-                }
-                Rule::triple_hash => {
-                    Expression::Prefixed(PrefixedExpression::TripleHash(Box::new(rhs)))
-                }
-                _ => unreachable!("{:#?}", op),
-            })
-            .map_postfix(|lhs, op| match op.as_rule() {
-                Rule::debug => Expression::Postfixed(PostfixedExpression::Debug(Box::new(lhs))),
-                Rule::print => Expression::Postfixed(PostfixedExpression::Print(Box::new(lhs))),
-                Rule::index => Expression::Postfixed(PostfixedExpression::Index(
-                    Box::new(lhs),
-                    Box::new(Expression::parse(op.childs())),
-                )),
-                Rule::dot_index => Expression::Postfixed(PostfixedExpression::DotIndex(
-                    Box::new(lhs),
-                    op.first_child().as_str().to_string(),
-                )),
-                _ => unreachable!("{:#?}", op),
-            })
-            .parse(pairs)
+        parse_operation(pairs.collect())
+    }
+}
+
+/// Assignments bind loosest and group to the right, so an expression with one is split at the
+/// first: the target before it, and everything after it as the assigned expression.
+fn parse_operation(mut pairs: Vec<Pair>) -> Expression {
+    if let Some(i) = pairs
+        .iter()
+        .position(|pair| set_op(pair.as_rule()).is_some())
+    {
+        let expression = parse_operation(pairs.split_off(i + 1));
+        let op = set_op(pairs.pop().unwrap().as_rule()).unwrap();
+        return Expression::Assign(AssignExpression {
+            span: Span::parse(pairs[0].clone()),
+            target: parse_target(pairs),
+            op,
+            expression: Box::new(expression),
+        });
+    }
+
+    // every operand comes with where it starts in the source, for `??` to show it as written
+    let (expression, _) = PRATT_PARSER
+        .map_primary(|primary| {
+            let start = primary.as_span().start();
+            // a parenthesized expression's span doesn't include the parentheses
+            let before = primary.get_input()[..start].trim_end();
+            let start = match before.strip_suffix('(') {
+                Some(before) if primary.as_rule() == Rule::expression => before.len(),
+                _ => start,
+            };
+            (parse_primary(primary), start)
+        })
+        .map_infix(|(lhs, start), op, (rhs, _)| (parse_infix(lhs, op, rhs), start))
+        .map_prefix(|op, (rhs, _)| {
+            let start = op.as_span().start();
+            (parse_prefix(op, rhs), start)
+        })
+        .map_postfix(|(lhs, start), op| (parse_postfix(lhs, op, start), start))
+        .parse(pairs.into_iter());
+    expression
+}
+
+fn parse_primary(primary: Pair) -> Expression {
+    match primary.as_rule() {
+        Rule::value => Expression::Value(Value::parse(primary.first_child())),
+        Rule::expression => Expression::parse(primary.childs()),
+        Rule::identifier => Expression::Identifier(primary.as_str().to_string()),
+        Rule::block => Expression::Block(Block::parse(primary)),
+        Rule::map => Expression::Map(MapExpression::parse(primary.childs())),
+        Rule::function_call => Expression::FunctionCall(FunctionCall::parse(primary.childs())),
+        Rule::array => Expression::Array(ExpressionsArray::parse(primary.childs())),
+        Rule::if_expr => Expression::If(IfExpression::parse(primary.childs())),
+        Rule::table => Expression::Table(ExpressionsTable::parse(primary.childs())),
+        Rule::set_literal => Expression::Set(ExpressionsArray::parse(primary.childs())),
+        _ => unreachable!("{:#?}", primary),
+    }
+}
+
+fn parse_infix(lhs: Expression, op: Pair, rhs: Expression) -> Expression {
+    Expression::Infixed(Operation {
+        lhs: Box::new(lhs),
+        infix: match op.as_rule() {
+            Rule::add => Operator::Add,
+            Rule::sub => Operator::Sub,
+            Rule::mul => Operator::Mul,
+            Rule::div => Operator::Div,
+            Rule::eq => Operator::Eq,
+            Rule::neq => Operator::Neq,
+            Rule::gt => Operator::Gt,
+            Rule::lt => Operator::Lt,
+            Rule::gte => Operator::Gte,
+            Rule::lte => Operator::Lte,
+            Rule::and => Operator::And,
+            Rule::or => Operator::Or,
+            Rule::coalesce => Operator::Coalesce,
+            Rule::dollar => Operator::Dollar,
+            Rule::double_dollar => Operator::DoubleDollar,
+            Rule::exclusive_range => Operator::ExclusiveRange,
+            Rule::inclusive_range => Operator::InclusiveRange,
+            Rule::modulo => Operator::Modulo,
+            Rule::at => Operator::At,
+            Rule::ampersand => Operator::Ampersand,
+            Rule::is_in => Operator::In,
+            _ => unreachable!("{:#?}", op),
+        },
+        rhs: Box::new(rhs),
+    })
+}
+
+fn parse_prefix(op: Pair, rhs: Expression) -> Expression {
+    match op.as_rule() {
+        Rule::negate => Expression::Prefixed(PrefixedExpression::Negative(Box::new(rhs))),
+        Rule::not => Expression::Prefixed(PrefixedExpression::Not(Box::new(rhs))),
+        Rule::hash => Expression::Prefixed(PrefixedExpression::Hash(Box::new(rhs))),
+        Rule::double_hash => Expression::Prefixed(PrefixedExpression::DoubleHash(Box::new(rhs))),
+        Rule::triple_hash => Expression::Prefixed(PrefixedExpression::TripleHash(Box::new(rhs))),
+        _ => unreachable!("{:#?}", op),
+    }
+}
+
+/// `start` is where `lhs` starts in the source.
+fn parse_postfix(lhs: Expression, op: Pair, start: usize) -> Expression {
+    match op.as_rule() {
+        Rule::debug => Expression::Postfixed(PostfixedExpression::Debug(
+            Box::new(lhs),
+            debug_info(&op, start),
+        )),
+        Rule::print => Expression::Postfixed(PostfixedExpression::Print(Box::new(lhs))),
+        Rule::index => Expression::Postfixed(PostfixedExpression::Index(
+            Box::new(lhs),
+            Box::new(Expression::parse(op.childs())),
+        )),
+        Rule::dot_index => Expression::Postfixed(PostfixedExpression::DotIndex(
+            Box::new(lhs),
+            op.first_child().as_str().to_string(),
+        )),
+        Rule::optional_dot_index => Expression::Postfixed(PostfixedExpression::OptionalDotIndex(
+            Box::new(lhs),
+            op.first_child().as_str().to_string(),
+        )),
+        Rule::call => Expression::Postfixed(PostfixedExpression::Call(
+            Box::new(lhs),
+            op.childs()
+                .map(|arg| Expression::parse(arg.childs()))
+                .collect(),
+        )),
+        _ => unreachable!("{:#?}", op),
     }
 }
 
@@ -575,7 +1146,7 @@ impl ParseSingle for Value {
     fn parse(pair: Pair) -> Self {
         match pair.as_rule() {
             Rule::closure => Value::Closure(Closure::parse(pair.childs())),
-            Rule::number => Value::Number(pair.as_str().parse().unwrap()),
+            Rule::number => Value::Number(parse_number(pair.as_str())),
             Rule::string => {
                 let str = pair.as_str();
                 Value::String(str[1..str.len() - 1].to_string())
@@ -586,3 +1157,108 @@ impl ParseSingle for Value {
         }
     }
 }
+
+/// Value of a `number` literal, `0x` and `0b` ones are integers in base 16 and 2.
+fn parse_number(literal: &str) -> f64 {
+    let digits = |digits: &str, radix| {
+        digits
+            .chars()
+            .filter_map(|digit| digit.to_digit(radix))
+            .fold(0., |n, digit| n * radix as f64 + digit as f64)
+    };
+    if let Some(hex) = literal.strip_prefix("0x") {
+        digits(hex, 16)
+    } else if let Some(binary) = literal.strip_prefix("0b") {
+        digits(binary, 2)
+    } else {
+        literal.parse().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{nodes::*, parse_number};
+
+    /// Text and column of the `??` `src` ends with.
+    fn debugged(src: &str) -> (String, usize) {
+        let program = crate::parser::parse(src).unwrap();
+        match &program.statements[0] {
+            Statement::Expression(Expression::Postfixed(PostfixedExpression::Debug(_, info))) => {
+                (info.text.clone(), info.column)
+            }
+            statement => panic!("{:?}", statement),
+        }
+    }
+
+    #[test]
+    fn debugged_expressions_as_written() {
+        assert_eq!(debugged("(a +  b)??"), ("(a +  b)".to_string(), 1));
+        assert_eq!(debugged("-x[0]??"), ("-x[0]".to_string(), 1));
+        assert_eq!(debugged("a.b ??"), ("a.b".to_string(), 1));
+        assert_eq!(debugged("f((1), 2) ???"), ("f((1), 2)".to_string(), 1));
+    }
+
+    #[test]
+    fn number_literals() {
+        assert_eq!(parse_number("0xFF"), 255.0);
+        assert_eq!(parse_number("0b1010"), 10.0);
+        assert_eq!(parse_number("2.5"), 2.5);
+        assert_eq!(parse_number("inf"), f64::INFINITY);
+    }
+
+    fn key(value: Value) -> Key {
+        Key::new(&value).unwrap()
+    }
+
+    #[test]
+    fn keys_fold_equal_numbers() {
+        assert_eq!(key(Value::Number(-0.0)), key(Value::Number(0.0)));
+        assert_eq!(key(Value::Number(f64::NAN)), key(Value::Number(-f64::NAN)));
+        assert_ne!(key(Value::Number(1.0)), key(Value::from("1")));
+    }
+
+    #[test]
+    fn only_plain_values_are_keys() {
+        assert!(Key::new(&Value::from(vec![vec![1]])).is_some());
+        assert!(Key::new(&Value::ExclusiveRange(0.0, 1.0)).is_none());
+        let table = Value::Table(ValuesTable {
+            map: Default::default(),
+        });
+        assert!(Key::new(&table).is_none());
+        // an array is only as good as its elements
+        assert!(Key::new(&Value::from(vec![table])).is_none());
+    }
+
+    #[test]
+    fn sets_iterate_in_order() {
+        let set = ValuesSet {
+            elements: [
+                Value::from("b"),
+                Value::Number(2.0),
+                Value::from(vec![1]),
+                Value::Nil,
+                Value::Number(-1.0),
+                Value::Boolean(true),
+            ]
+            .iter()
+            .map(|value| Key::new(value).unwrap())
+            .collect(),
+        };
+        let values = set
+            .sorted()
+            .into_iter()
+            .map(Key::to_value)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                Value::Nil,
+                Value::Boolean(true),
+                Value::Number(-1.0),
+                Value::Number(2.0),
+                Value::from("b"),
+                Value::from(vec![1]),
+            ]
+        );
+    }
+}
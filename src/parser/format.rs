@@ -0,0 +1,80 @@
+use super::{
+    parse_named,
+    pest::{PestParser, Rule},
+    unparse::{unparse_with_trivia, Comment, Trivia},
+    ParseError,
+};
+use pest::Parser;
+
+/// `input` in the style `unparse` prints programs, comments and single blank lines kept.
+pub fn format_source(input: &str, name: &str) -> Result<String, ParseError> {
+    let program = parse_named(input, name)?;
+    let trivia = Trivia {
+        comments: comments(input).into(),
+        blank_lines: input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.trim().is_empty())
+            .map(|(i, _)| i + 1)
+            .collect(),
+    };
+    let src = unparse_with_trivia(&program, trivia);
+    Ok(if src.is_empty() { src } else { src + "\n" })
+}
+
+/// Every comment in `input`, which doesn't need to parse.
+pub(super) fn comments(input: &str) -> Vec<Comment> {
+    let Ok(pairs) = PestParser::parse(Rule::comments, input) else {
+        return vec![];
+    };
+    pairs
+        .flatten()
+        .filter(|pair| pair.as_rule() == Rule::comment)
+        .map(|pair| {
+            let (line, column) = pair.line_col();
+            let line_start = input[..pair.as_span().start()]
+                .rfind('\n')
+                .map_or(0, |i| i + 1);
+            Comment {
+                text: pair.as_str().trim_end().to_string(),
+                line,
+                column,
+                trailing: !input[line_start..pair.as_span().start()].trim().is_empty(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSY: &str = "// header\nx:=1   // what\n\n\n\nfn f(a){a+1} /* trailing */\ny := f(x)";
+
+    #[test]
+    fn keeps_comments_and_single_blank_lines() {
+        assert_eq!(
+            format_source(MESSY, "test").unwrap(),
+            "// header\nx := 1 // what\n\nfn f(a) {\n  a + 1\n} /* trailing */\ny := f(x)\n"
+        );
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let formatted = format_source(MESSY, "test").unwrap();
+        assert_eq!(format_source(&formatted, "test").unwrap(), formatted);
+        assert_eq!(format_source("", "test").unwrap(), "");
+    }
+
+    #[test]
+    fn comment_markers_in_strings_are_not_comments() {
+        let src = "url := \"http://example.com\" // home\n";
+        assert_eq!(format_source(src, "test").unwrap(), src);
+        assert_eq!(comments(src).len(), 1);
+    }
+
+    #[test]
+    fn syntax_errors_are_returned() {
+        assert!(format_source("x := (", "test").is_err());
+    }
+}
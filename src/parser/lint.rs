@@ -0,0 +1,334 @@
+//! Checks for code that runs but likely doesn't do what was meant.
+
+use super::{
+    analysis::{analyze, is_infix, range, Analysis, Diagnostic, Severity, SymbolKind},
+    format::comments,
+    pest::{is_assignment, parse, Pair, Rule},
+};
+use std::collections::{HashMap, HashSet};
+
+/// ID, severity and description of each rule, on top of the `syntax` errors of `analyze` and
+/// its `undefined` and `arity` warnings, errors under `"use strict"`.
+pub const RULES: &[(&str, Severity, &str)] = &[
+    (
+        "implicit-global",
+        Severity::Warning,
+        "`=` assigning a name nothing defined, which makes a global where `:=` was likely meant",
+    ),
+    (
+        "unused-variable",
+        Severity::Warning,
+        "a variable of a function or block that's never read, names starting with _ are left out",
+    ),
+    (
+        "shadowing",
+        Severity::Warning,
+        "a variable of a function or block hiding one of an outer scope",
+    ),
+    (
+        "type-mismatch",
+        Severity::Error,
+        "`==` or `!=` between literals of different types, always false or always true",
+    ),
+    (
+        "unreachable-arm",
+        Severity::Warning,
+        "a `map` arm after `any name` without a guard, which matches everything",
+    ),
+];
+
+fn severity(rule: &str) -> Severity {
+    RULES
+        .iter()
+        .find(|(id, ..)| *id == rule)
+        .map_or(Severity::Error, |(_, severity, _)| *severity)
+}
+
+/// Problems found in `input`, in source order. `builtins` are the names the host defines.
+/// A `// lint-ignore` comment silences the line it ends or, alone on its line, the next one;
+/// `// lint-ignore: rule, other-rule` only silences those rules.
+pub fn lint(input: &str, builtins: &HashSet<String>) -> Vec<Diagnostic> {
+    lint_analysis(input, &analyze(input, builtins))
+}
+
+/// Like `lint`, with the `analyze` of `input` already made.
+pub fn lint_analysis(input: &str, analysis: &Analysis) -> Vec<Diagnostic> {
+    let mut diagnostics = analysis.diagnostics.clone();
+    symbol_rules(input, analysis, &mut diagnostics);
+    if let Ok(pairs) = parse(input) {
+        pairs.for_each(|pair| walk(pair, &mut diagnostics));
+    }
+
+    let ignored = ignored_lines(input);
+    diagnostics.retain(
+        |diagnostic| match ignored.get(&line_of(input, diagnostic.range.start)) {
+            Some(rules) => !rules.is_empty() && !rules.iter().any(|rule| rule == diagnostic.rule),
+            None => true,
+        },
+    );
+    diagnostics.sort_by_key(|diagnostic| diagnostic.range.start);
+    diagnostics
+}
+
+fn line_of(input: &str, offset: usize) -> usize {
+    input[..offset].matches('\n').count() + 1
+}
+
+fn push(diagnostics: &mut Vec<Diagnostic>, rule: &'static str, pair: &Pair<Rule>, message: String) {
+    diagnostics.push(Diagnostic {
+        range: range(pair),
+        severity: severity(rule),
+        rule,
+        message,
+    });
+}
+
+fn symbol_rules(input: &str, analysis: &Analysis, diagnostics: &mut Vec<Diagnostic>) {
+    for (i, symbol) in analysis.symbols.iter().enumerate() {
+        let mut report = |rule, message| {
+            diagnostics.push(Diagnostic {
+                range: symbol.range.clone(),
+                severity: severity(rule),
+                rule,
+                message,
+            })
+        };
+        if symbol.implicit {
+            report(
+                "implicit-global",
+                format!(
+                    "{0} isn't defined, so this makes it a global, use {0} := to define it here",
+                    symbol.name
+                ),
+            );
+        }
+        // globals can be used by importers and the REPL
+        if symbol.kind != SymbolKind::Variable || symbol.global {
+            continue;
+        }
+        let read = analysis
+            .references
+            .iter()
+            .any(|reference| reference.symbol == i && !reference.write);
+        if !read && !symbol.name.starts_with('_') {
+            report("unused-variable", format!("{} is never read", symbol.name));
+        }
+        if let Some(outer) = symbol.shadows {
+            let line = line_of(input, analysis.symbols[outer].range.start);
+            report(
+                "shadowing",
+                format!("{} hides the one defined on line {}", symbol.name, line),
+            );
+        }
+    }
+}
+
+fn walk(pair: Pair<Rule>, diagnostics: &mut Vec<Diagnostic>) {
+    match pair.as_rule() {
+        Rule::expression => comparisons(&pair, diagnostics),
+        Rule::map => unreachable_arms(&pair, diagnostics),
+        _ => (),
+    }
+    pair.into_inner().for_each(|pair| walk(pair, diagnostics));
+}
+
+/// `==` and `!=` between literals of different types.
+fn comparisons(expression: &Pair<Rule>, diagnostics: &mut Vec<Diagnostic>) {
+    let mut operands = vec![vec![]];
+    let mut infixes = vec![];
+    for part in expression.clone().into_inner() {
+        if is_infix(part.as_rule()) || is_assignment(part.as_rule()) {
+            infixes.push(part.as_rule());
+            operands.push(vec![]);
+        } else {
+            operands.last_mut().unwrap().push(part);
+        }
+    }
+    // only when the operators around it bind looser, so the operands are whole
+    let loose = |rule: Option<&Rule>| {
+        rule.is_none_or(|rule| {
+            matches!(rule, Rule::coalesce | Rule::and | Rule::or) || is_assignment(*rule)
+        })
+    };
+    for (i, operator) in infixes.iter().enumerate() {
+        if !matches!(operator, Rule::eq | Rule::neq)
+            || !loose(i.checked_sub(1).map(|i| &infixes[i]))
+            || !loose(infixes.get(i + 1))
+        {
+            continue;
+        }
+        let (lhs, rhs) = (&operands[i], &operands[i + 1]);
+        let (Some(lhs_kind), Some(rhs_kind)) = (literal_kind(lhs), literal_kind(rhs)) else {
+            continue;
+        };
+        if lhs_kind != rhs_kind {
+            let always = if *operator == Rule::eq {
+                "false"
+            } else {
+                "true"
+            };
+            diagnostics.push(Diagnostic {
+                range: range(&lhs[0]).start..range(rhs.last().unwrap()).end,
+                severity: severity("type-mismatch"),
+                rule: "type-mismatch",
+                message: format!(
+                    "comparing a {} with a {} is always {}",
+                    lhs_kind, rhs_kind, always
+                ),
+            });
+        }
+    }
+}
+
+/// Type of an operand that's a literal, printing it aside.
+fn literal_kind(parts: &[Pair<Rule>]) -> Option<&'static str> {
+    let parts = parts
+        .iter()
+        .filter(|part| !matches!(part.as_rule(), Rule::print | Rule::debug))
+        .collect::<Vec<_>>();
+    let kind = |part: &Pair<Rule>| match part.as_rule() {
+        Rule::value => match part.clone().into_inner().next()?.as_rule() {
+            Rule::number => Some("number"),
+            Rule::string => Some("string"),
+            Rule::boolean => Some("boolean"),
+            Rule::nil => Some("nil"),
+            _ => None,
+        },
+        Rule::array => Some("array"),
+        Rule::table => Some("table"),
+        Rule::set_literal => Some("set"),
+        _ => None,
+    };
+    match parts[..] {
+        [part] => kind(part),
+        [prefix, part] if prefix.as_rule() == Rule::negate => {
+            kind(part).filter(|kind| *kind == "number")
+        }
+        _ => None,
+    }
+}
+
+/// Arms after one matching everything, as the first arm that matches is taken.
+fn unreachable_arms(map: &Pair<Rule>, diagnostics: &mut Vec<Diagnostic>) {
+    let mut matched_all = false;
+    for arm in map
+        .clone()
+        .into_inner()
+        .filter(|pair| pair.as_rule() == Rule::map_arm)
+    {
+        let mut inner = arm.into_inner();
+        let cases = inner.next().unwrap();
+        if matched_all {
+            push(
+                diagnostics,
+                "unreachable-arm",
+                &cases,
+                "unreachable arm, an arm above matches everything".to_string(),
+            );
+        }
+        // a guard can still turn the value down
+        let guarded = inner
+            .next()
+            .is_some_and(|pair| pair.as_rule() == Rule::guard);
+        matched_all |= !guarded && cases.into_inner().any(|case| matches_all(&case));
+    }
+}
+
+/// `any name`, arms after `_` are already a parse error.
+fn matches_all(pattern: &Pair<Rule>) -> bool {
+    let pattern = pattern.clone().into_inner().next().unwrap();
+    pattern.as_rule() == Rule::type_pattern
+        && pattern.into_inner().next().unwrap().as_str() == "any"
+}
+
+/// Rules silenced on each line, an empty list silences them all.
+fn ignored_lines(input: &str) -> HashMap<usize, Vec<String>> {
+    let mut ignored = HashMap::new();
+    for comment in comments(input) {
+        let text = comment
+            .text
+            .trim_start_matches("//")
+            .trim_start_matches("/*")
+            .trim_end_matches("*/")
+            .trim();
+        let Some(rules) = text.strip_prefix("lint-ignore") else {
+            continue;
+        };
+        let rules = rules
+            .trim_start_matches(':')
+            .split(',')
+            .map(|rule| rule.trim().to_string())
+            .filter(|rule| !rule.is_empty())
+            .collect();
+        let line = match comment.trailing {
+            true => comment.line,
+            false => comment.line + 1,
+        };
+        ignored.insert(line, rules);
+    }
+    ignored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(input: &str) -> Vec<&'static str> {
+        lint(input, &HashSet::from(["print".to_string()]))
+            .iter()
+            .map(|diagnostic| diagnostic.rule)
+            .collect()
+    }
+
+    #[test]
+    fn implicit_global() {
+        assert_eq!(rules("fn f() {\n  count = 1\n}"), ["implicit-global"]);
+        assert!(rules("count := 0\nfn f() {\n  count = 1\n}").is_empty());
+    }
+
+    #[test]
+    fn unused_variable() {
+        assert_eq!(rules("fn f() {\n  x := 1\n}"), ["unused-variable"]);
+        assert!(rules("fn f() {\n  _x := 1\n}").is_empty());
+        // globals may be read by importers
+        assert!(rules("x := 1").is_empty());
+    }
+
+    #[test]
+    fn shadowing() {
+        let src = "x := 1\nfn f() {\n  x := 2\n  x\n}";
+        let diagnostics = lint(src, &HashSet::new());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].rule, "shadowing");
+        assert_eq!(diagnostics[0].message, "x hides the one defined on line 1");
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let diagnostics = lint("1 == \"1\"", &HashSet::new());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            (diagnostics[0].rule, diagnostics[0].severity),
+            ("type-mismatch", Severity::Error)
+        );
+        assert!(rules("1 == 2").is_empty());
+    }
+
+    #[test]
+    fn unreachable_arm() {
+        assert_eq!(
+            rules("map 1 {\n  any x => x\n  1 => 2\n}"),
+            ["unreachable-arm"]
+        );
+        assert!(rules("map 1 {\n  any x if x > 0 => x\n  1 => 2\n}").is_empty());
+    }
+
+    #[test]
+    fn lint_ignore() {
+        let src =
+            "fn f() {\n  x := 1 // lint-ignore\n  // lint-ignore: unused-variable\n  y := 2\n}";
+        assert!(rules(src).is_empty());
+        let other = "fn f() {\n  x := 1 // lint-ignore: shadowing\n}";
+        assert_eq!(rules(other), ["unused-variable"]);
+    }
+}
@@ -1,14 +1,28 @@
+pub mod analysis;
 mod ast;
+mod format;
+pub mod lint;
 mod pest;
+mod unparse;
 
 pub use ast::nodes::*;
+pub use format::*;
+pub use unparse::*;
 use ast::ParseMulti;
 
-pub fn parse(input: &str) -> Program {
-    let pairs = pest::parse(input);
+pub type ParseError = Box<pest::Error>;
+
+pub fn parse(input: &str) -> Result<Program, ParseError> {
+    parse_named(input, "<input>")
+}
+
+/// Like `parse`, with `name` used in errors and `??` output.
+pub fn parse_named(input: &str, name: &str) -> Result<Program, ParseError> {
+    ast::set_source_name(name);
+    let pairs = pest::parse(input).map_err(|e| Box::new(e.with_path(name)))?;
     // println!("{:#?}", pairs);
     #[allow(clippy::let_and_return)]
     let program = Program::parse(pairs);
     // println!("{:#?}", program);
-    program
+    Ok(program)
 }
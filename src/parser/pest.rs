@@ -2,40 +2,171 @@
 pub use pest::iterators::{Pair, Pairs};
 use pest::{pratt_parser::PrattParser, Parser};
 use pest_derive::Parser;
-use std::process::exit;
 
 #[derive(Parser)]
 #[grammar = "parser/grammar.pest"]
 pub struct PestParser;
 
-pub fn parse(input: &str) -> Pairs<Rule> {
-    match PestParser::parse(Rule::program, input) {
-        Ok(pairs) => pairs,
+pub type Error = pest::error::Error<Rule>;
+
+pub fn parse(input: &str) -> Result<Pairs<'_, Rule>, Box<Error>> {
+    let pairs = PestParser::parse(Rule::program, input).map_err(Box::new)?;
+    for pair in pairs.clone().flatten() {
+        match pair.as_rule() {
+            Rule::expression => check_targets(pair)?,
+            Rule::array_pattern => check_rest(pair)?,
+            Rule::function_args | Rule::closure_args => check_variadic(pair)?,
+            Rule::regex => check_regex(pair)?,
+            Rule::map => check_arms(pair)?,
+            _ => (),
+        }
+    }
+    Ok(pairs)
+}
+
+/// Whether `rule` is `:=`, `=`, `+=` or `-=`.
+pub fn is_assignment(rule: Rule) -> bool {
+    matches!(
+        rule,
+        Rule::define | Rule::set | Rule::increment | Rule::decrement
+    )
+}
+
+/// Everything on the left of an assignment must be a name, or for all but `:=` a name followed
+/// by indexes: `x`, `x[0]`, `x.key`.
+fn check_targets(expression: Pair<'_, Rule>) -> Result<(), Box<Error>> {
+    let parts = expression.into_inner().collect::<Vec<_>>();
+    let mut start = 0;
+    for (i, op) in parts.iter().enumerate() {
+        if !is_assignment(op.as_rule()) {
+            continue;
+        }
+        let target = &parts[start..i];
+        start = i + 1;
+        let indexes = &target[1..];
+        let message = match target[0].as_rule() {
+            Rule::identifier if indexes.is_empty() => continue,
+            Rule::identifier if op.as_rule() == Rule::define => {
+                "`:=` defines a name, use `=` to change an element"
+            }
+            Rule::identifier
+                if indexes
+                    .iter()
+                    .all(|pair| matches!(pair.as_rule(), Rule::index | Rule::dot_index)) =>
+            {
+                continue
+            }
+            _ => "can only assign to a name or an element of one, like `x`, `x[0]` or `x.key`",
+        };
+        let span = target[0]
+            .as_span()
+            .start_pos()
+            .span(&target[target.len() - 1].as_span().end_pos());
+        return Err(custom_error(message.to_string(), span));
+    }
+    Ok(())
+}
+
+/// An array pattern can take the elements left over only once.
+fn check_rest(pattern: Pair<'_, Rule>) -> Result<(), Box<Error>> {
+    match pattern
+        .into_inner()
+        .filter(|pair| pair.as_rule() == Rule::rest_pattern)
+        .nth(1)
+    {
+        Some(rest) => Err(custom_error(
+            "an array pattern can only have one `...` element".to_string(),
+            rest.as_span(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Only the last parameter can take the arguments left over.
+fn check_variadic(parameters: Pair<'_, Rule>) -> Result<(), Box<Error>> {
+    let parameters = parameters.into_inner().collect::<Vec<_>>();
+    let rest = parameters.iter().position(|parameter| {
+        parameter.clone().into_inner().next().unwrap().as_rule() == Rule::rest_pattern
+    });
+    match rest {
+        Some(i) if i + 1 < parameters.len() => Err(custom_error(
+            "only the last parameter can be a `...` one".to_string(),
+            parameters[i].as_span(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Arms are tried in order, nothing after an unguarded `_` is ever reached. It used to be the
+/// fallback wherever it was, so this fails instead of quietly changing what old code does.
+fn check_arms(map: Pair<'_, Rule>) -> Result<(), Box<Error>> {
+    let arms = map
+        .into_inner()
+        .filter(|pair| pair.as_rule() == Rule::map_arm)
+        .collect::<Vec<_>>();
+    let wildcard = arms.iter().position(|arm| {
+        let mut parts = arm.clone().into_inner();
+        let cases = parts.next().unwrap();
+        let guarded = parts
+            .next()
+            .is_some_and(|part| part.as_rule() == Rule::guard);
+        !guarded
+            && cases
+                .into_inner()
+                .any(|pattern| pattern.as_str().trim() == "_")
+    });
+    match wildcard {
+        Some(i) if i + 1 < arms.len() => Err(custom_error(
+            "unreachable arm, the `_` above matches everything, move it last".to_string(),
+            arms[i + 1].as_span(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn check_regex(regex: Pair<'_, Rule>) -> Result<(), Box<Error>> {
+    let str = regex.as_str();
+    match regex::Regex::new(&str[1..str.len() - 1].replace("\\/", "/")) {
+        Ok(_) => Ok(()),
+        // the last line of the message says what's wrong, the others point at where
         Err(e) => {
-            eprintln!("{}", e);
-            exit(1);
+            let message = e.to_string();
+            let reason = message.lines().last().unwrap_or_default();
+            Err(custom_error(
+                format!("invalid regex, {}", reason.trim_start_matches("error: ")),
+                regex.as_span(),
+            ))
         }
     }
 }
 
+fn custom_error(message: String, span: pest::Span<'_>) -> Box<Error> {
+    Box::new(Error::new_from_span(
+        pest::error::ErrorVariant::CustomError { message },
+        span,
+    ))
+}
+
 lazy_static::lazy_static! {
     pub static ref PRATT_PARSER: PrattParser<Rule> = {
         use pest::pratt_parser::{Assoc::*, Op};
         use Rule::*;
 
         PrattParser::new()
+            .op(Op::infix(coalesce, Right))
             .op(Op::infix(or, Left))
             .op(Op::infix(and, Left))
             .op(Op::infix(eq, Left) | Op::infix(neq, Left) |
                 Op::infix(lt, Left) | Op::infix(gt, Left) |
-                Op::infix(lte, Left) | Op::infix(gte, Left))
+                Op::infix(lte, Left) | Op::infix(gte, Left) | Op::infix(is_in, Left))
             .op(Op::infix(exclusive_range, Left) | Op::infix(inclusive_range, Left))
             .op(Op::infix(add, Left) | Op::infix(sub, Left))
             .op(Op::infix(mul, Left) | Op::infix(div, Left) |
-                Op::infix(modulo, Left) | Op::infix(at, Left))
+                Op::infix(modulo, Left) | Op::infix(at, Left) | Op::infix(ampersand, Left))
             .op(Op::infix(dollar, Left) | Op::infix(double_dollar, Left))
             .op(Op::postfix(debug) | Op::postfix(print))
-            .op(Op::postfix(index) | Op::postfix(dot_index))
+            .op(Op::postfix(index) | Op::postfix(dot_index) | Op::postfix(optional_dot_index) |
+                Op::postfix(call))
             .op(Op::prefix(negate) | Op::prefix(not) |
                 Op::prefix(triple_hash) | Op::prefix(double_hash) | Op::prefix(hash))
     };
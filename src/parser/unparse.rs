@@ -0,0 +1,676 @@
+use super::ast::nodes::*;
+use std::{
+    cell::RefCell,
+    collections::{HashSet, VecDeque},
+};
+
+const INDENT: &str = "  ";
+
+/// Turns syntax tree nodes back into qs source code.
+pub trait Unparse {
+    fn unparse_at(&self, indent: usize) -> String;
+
+    fn unparse(&self) -> String {
+        self.unparse_at(0)
+    }
+}
+
+fn indent(level: usize) -> String {
+    INDENT.repeat(level)
+}
+
+pub fn fmt_number(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        if value > 0. { "inf" } else { "-inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Quotes a string the way it appeared in source, strings aren't unescaped when parsed.
+pub fn fmt_string_literal(value: &str) -> String {
+    if value.contains('"') && !value.contains('\'') {
+        format!("'{}'", value)
+    } else {
+        format!("\"{}\"", value)
+    }
+}
+
+/// A table key as written in a table literal, quoted unless it's a valid name.
+pub fn fmt_table_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        key.to_string()
+    } else {
+        fmt_string_literal(key)
+    }
+}
+
+/// Parameters of a closure or `fn` as written between the bars or parentheses.
+pub fn fmt_parameters(parameters: &[Parameter]) -> String {
+    parameters
+        .iter()
+        .map(|parameter| match &parameter.default {
+            Some(default) => format!("{} = {}", parameter.pattern.unparse(), default.unparse()),
+            None => parameter.pattern.unparse(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A comment found by `format_source`, comments aren't part of the syntax tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+    /// Whether code comes before it on its line.
+    pub trailing: bool,
+}
+
+/// What unparsing puts back between statements when formatting.
+#[derive(Debug, Clone, Default)]
+pub struct Trivia {
+    /// Comments not placed yet, in source order.
+    pub comments: VecDeque<Comment>,
+    pub blank_lines: HashSet<usize>,
+}
+
+thread_local! {
+    /// Set while `unparse_with_trivia` runs.
+    static TRIVIA: RefCell<Trivia> = RefCell::new(Trivia::default());
+}
+
+/// Like `unparse`, with comments placed before or after the statements they were next to and
+/// single blank lines kept.
+pub fn unparse_with_trivia(program: &Program, trivia: Trivia) -> String {
+    TRIVIA.with(|t| *t.borrow_mut() = trivia);
+    let src = program.unparse();
+    TRIVIA.with(|t| std::mem::take(&mut *t.borrow_mut()));
+    src
+}
+
+/// Takes the comments starting before `position`, all of them when it's `None`.
+fn take_comments(position: Option<(usize, usize)>, trailing_only: bool) -> Vec<Comment> {
+    TRIVIA.with(|trivia| {
+        let comments = &mut trivia.borrow_mut().comments;
+        let mut taken = vec![];
+        while let Some(comment) = comments.front() {
+            let before = position.is_none_or(|position| (comment.line, comment.column) < position);
+            if !before || (trailing_only && !comment.trailing) {
+                break;
+            }
+            taken.extend(comments.pop_front());
+        }
+        taken
+    })
+}
+
+fn is_blank_line(line: usize) -> bool {
+    TRIVIA.with(|trivia| trivia.borrow().blank_lines.contains(&line))
+}
+
+/// Adds a blank line if there was one before `line`, the source has at most one in a row kept.
+fn push_blank_line(lines: &mut Vec<String>, line: usize) {
+    if !lines.is_empty() && is_blank_line(line - 1) {
+        lines.push(String::new());
+    }
+}
+
+fn push_comments(lines: &mut Vec<String>, comments: Vec<Comment>) {
+    for comment in comments {
+        push_blank_line(lines, comment.line);
+        lines.push(comment.text);
+    }
+}
+
+enum Item<'a> {
+    Function(&'a Function),
+    Statement(&'a Statement),
+    Test(&'a Test),
+}
+
+/// Functions, statements and tests in source order, with the comments that come before `end`.
+fn unparse_body(
+    functions: &[Function],
+    statements: &[Statement],
+    spans: &[Span],
+    tests: &[Test],
+    end: Option<&Span>,
+    level: usize,
+) -> String {
+    let mut items = functions
+        .iter()
+        .map(|f| (Some(&f.span), Item::Function(f)))
+        .chain(
+            statements
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (spans.get(i), Item::Statement(s))),
+        )
+        .chain(tests.iter().map(|t| (Some(&t.span), Item::Test(t))))
+        .collect::<Vec<_>>();
+    let position = |span: Option<&Span>| span.map(|span| (span.line, span.column));
+    items.sort_by_key(|(span, _)| position(*span));
+
+    let mut lines = vec![];
+    for (i, (span, item)) in items.iter().enumerate() {
+        let start = position(*span);
+        push_comments(&mut lines, take_comments(start, false));
+        if let Some((line, _)) = start {
+            push_blank_line(&mut lines, line);
+        }
+        let mut src = match item {
+            Item::Function(f) => f.unparse_at(level),
+            Item::Statement(s) => s.unparse_at(level),
+            Item::Test(t) => format!(
+                "test {} {}",
+                fmt_string_literal(&t.name),
+                t.body.unparse_at(level)
+            ),
+        };
+        let next = items.get(i + 1).map_or(end, |(span, _)| *span);
+        for comment in take_comments(position(next), true) {
+            src = format!("{} {}", src, comment.text);
+        }
+        lines.push(src);
+    }
+    push_comments(&mut lines, take_comments(position(end), false));
+    lines
+        .into_iter()
+        .map(|line| match line.is_empty() {
+            true => line,
+            false => format!("{}{}", indent(level), line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Body of `if`, `while` and `for`, blocks stay on the same line, anything else is indented below.
+fn unparse_branch(statement: &Statement, level: usize) -> String {
+    match statement {
+        Statement::Expression(Expression::Block(block)) => format!(" {}", block.unparse_at(level)),
+        _ => format!("\n{}{}", indent(level + 1), statement.unparse_at(level + 1)),
+    }
+}
+
+/// `} else` after a block, anything else needs `else` on the next line.
+fn else_separator(branch: &Statement, level: usize) -> String {
+    match branch {
+        Statement::Expression(Expression::Block(_)) => " ".to_string(),
+        _ => format!("\n{}", indent(level)),
+    }
+}
+
+impl Unparse for Program {
+    fn unparse_at(&self, indent: usize) -> String {
+        unparse_body(
+            &self.functions,
+            &self.statements,
+            &self.spans,
+            &self.tests,
+            None,
+            indent,
+        )
+    }
+}
+
+impl Unparse for Block {
+    fn unparse_at(&self, level: usize) -> String {
+        let body = unparse_body(
+            &self.functions,
+            &self.statements,
+            &self.spans,
+            &[],
+            Some(&self.end),
+            level + 1,
+        );
+        if body.is_empty() {
+            return "{\n}".to_string();
+        }
+        format!("{{\n{}\n{}}}", body, indent(level))
+    }
+}
+
+impl Unparse for Target {
+    fn unparse_at(&self, indent: usize) -> String {
+        let mut src = self.identifier.clone();
+        for accessor in &self.path {
+            match accessor {
+                Accessor::Index(index) => src += &format!("[{}]", index.unparse_at(indent)),
+                Accessor::Key(key) => src += &format!(".{}", key),
+            }
+        }
+        src
+    }
+}
+
+impl Unparse for Pattern {
+    fn unparse_at(&self, indent: usize) -> String {
+        match self {
+            Pattern::Wildcard => "_".to_string(),
+            Pattern::Binding(name) => name.clone(),
+            Pattern::Value(value) => value.unparse_at(indent),
+            Pattern::Type(kind, name) => format!("{} {}", kind, name),
+            Pattern::Array(patterns) => format!(
+                "[{}]",
+                patterns
+                    .iter()
+                    .map(|p| p.unparse_at(indent))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Pattern::Rest(name) => format!("...{}", name),
+            Pattern::Table(fields) => format!(
+                "{{{}}}",
+                fields
+                    .iter()
+                    .map(|(key, pattern)| match pattern {
+                        Pattern::Binding(name) if name == key => key.clone(),
+                        _ => format!("{}: {}", fmt_table_key(key), pattern.unparse_at(indent)),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Pattern::Regex(source) => format!("/{}/", source.replace('/', "\\/")),
+        }
+    }
+}
+
+impl Unparse for Function {
+    fn unparse_at(&self, indent: usize) -> String {
+        format!(
+            "fn {}({}) {}",
+            self.name,
+            fmt_parameters(&self.closure.arguments),
+            self.closure.body.unparse_at(indent)
+        )
+    }
+}
+
+impl Unparse for Statement {
+    fn unparse_at(&self, indent: usize) -> String {
+        match self {
+            Statement::Expression(expression) => expression.unparse_at(indent),
+            Statement::Define(define) => format!("let {}", define.identifier),
+            Statement::While(w) => format!(
+                "while {}{}",
+                w.expression.unparse_at(indent),
+                unparse_branch(&w.statement, indent)
+            ),
+            Statement::For(f) => format!(
+                "for ({}; {}; {}){}",
+                f.initializer.unparse_at(indent),
+                f.condition.unparse_at(indent),
+                f.increment.unparse_at(indent),
+                unparse_branch(&f.statement, indent)
+            ),
+            Statement::Reference(reference) => {
+                format!("{} -> {}", reference.identifier, reference.target)
+            }
+            Statement::Destructure(destructure) => format!(
+                "{} := {}",
+                destructure.pattern.unparse_at(indent),
+                destructure.expression.unparse_at(indent)
+            ),
+            Statement::Import(import) => match &import.name {
+                Some(name) => format!("import {} as {}", fmt_string_literal(&import.path), name),
+                None => format!("import {}", fmt_string_literal(&import.path)),
+            },
+        }
+    }
+}
+
+/// Binding power of an expression, higher binds tighter, mirrors `PRATT_PARSER`.
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Infixed(operation) => match operation.infix {
+            Operator::Coalesce => 1,
+            Operator::Or => 2,
+            Operator::And => 3,
+            Operator::Eq
+            | Operator::Neq
+            | Operator::Lt
+            | Operator::Gt
+            | Operator::Lte
+            | Operator::Gte
+            | Operator::In => 4,
+            Operator::ExclusiveRange | Operator::InclusiveRange => 5,
+            Operator::Add | Operator::Sub => 6,
+            Operator::Mul
+            | Operator::Div
+            | Operator::Modulo
+            | Operator::At
+            | Operator::Ampersand => 7,
+            Operator::Dollar | Operator::DoubleDollar => 8,
+        },
+        Expression::Postfixed(PostfixedExpression::Debug(..) | PostfixedExpression::Print(..)) => 9,
+        Expression::Postfixed(..) => 10,
+        Expression::Prefixed(..) => 11,
+        // closures swallow everything after the argument list
+        Expression::Value(Value::Closure(..)) | Expression::Assign(..) => 0,
+        _ => u8::MAX,
+    }
+}
+
+fn wrap(expression: &Expression, min: u8, indent: usize) -> String {
+    let src = expression.unparse_at(indent);
+    if precedence(expression) < min {
+        format!("({})", src)
+    } else {
+        src
+    }
+}
+
+impl Operator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Eq => "==",
+            Operator::Neq => "!=",
+            Operator::Gt => ">",
+            Operator::Lt => "<",
+            Operator::Gte => ">=",
+            Operator::Lte => "<=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Coalesce => "?:",
+            Operator::Dollar => "$",
+            Operator::DoubleDollar => "$$",
+            Operator::ExclusiveRange => "..",
+            Operator::InclusiveRange => "...",
+            Operator::Modulo => "%",
+            Operator::At => "@",
+            Operator::Ampersand => "&",
+            Operator::In => "in",
+        }
+    }
+}
+
+impl SetOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            SetOp::Define => ":=",
+            SetOp::Set => "=",
+            SetOp::Increment => "+=",
+            SetOp::Decrement => "-=",
+        }
+    }
+}
+
+impl PrefixedExpression {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            PrefixedExpression::Negative(_) => "-",
+            PrefixedExpression::Not(_) => "!",
+            PrefixedExpression::Hash(_) => "#",
+            PrefixedExpression::DoubleHash(_) => "##",
+            PrefixedExpression::TripleHash(_) => "###",
+        }
+    }
+}
+
+impl Unparse for Expression {
+    fn unparse_at(&self, indent: usize) -> String {
+        let prec = precedence(self);
+        match self {
+            Expression::Value(value) => value.unparse_at(indent),
+            Expression::Infixed(operation) => {
+                let space = match operation.infix {
+                    Operator::ExclusiveRange | Operator::InclusiveRange => "",
+                    _ => " ",
+                };
+                // `?:` groups to the right, the others to the left
+                let (lhs, rhs) = match operation.infix {
+                    Operator::Coalesce => (prec + 1, prec),
+                    _ => (prec, prec + 1),
+                };
+                format!(
+                    "{}{space}{}{space}{}",
+                    wrap(&operation.lhs, lhs, indent),
+                    operation.infix.symbol(),
+                    wrap(&operation.rhs, rhs, indent)
+                )
+            }
+            Expression::Prefixed(prefixed) => {
+                let expression = match prefixed {
+                    PrefixedExpression::Negative(e)
+                    | PrefixedExpression::Not(e)
+                    | PrefixedExpression::Hash(e)
+                    | PrefixedExpression::DoubleHash(e)
+                    | PrefixedExpression::TripleHash(e) => e,
+                };
+                format!("{}{}", prefixed.symbol(), wrap(expression, prec, indent))
+            }
+            Expression::Postfixed(postfixed) => match postfixed {
+                PostfixedExpression::Debug(e, info) => {
+                    format!("{}{}", wrap(e, prec, indent), "?".repeat(info.level + 1))
+                }
+                PostfixedExpression::Print(e) => format!("{}?", wrap(e, prec, indent)),
+                PostfixedExpression::Index(e, index) => format!(
+                    "{}[{}]",
+                    wrap(e, prec, indent),
+                    index.unparse_at(indent)
+                ),
+                PostfixedExpression::DotIndex(e, key) => {
+                    format!("{}.{}", wrap(e, prec, indent), key)
+                }
+                PostfixedExpression::OptionalDotIndex(e, key) => {
+                    format!("{}?.{}", wrap(e, prec, indent), key)
+                }
+                PostfixedExpression::Call(e, arguments) => format!(
+                    "{}({})",
+                    wrap(e, prec, indent),
+                    arguments
+                        .iter()
+                        .map(|a| a.unparse_at(indent))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            },
+            Expression::Assign(assignment) => format!(
+                "{} {} {}",
+                assignment.target.unparse_at(indent),
+                assignment.op.symbol(),
+                assignment.expression.unparse_at(indent)
+            ),
+            Expression::Identifier(identifier) => identifier.clone(),
+            Expression::Block(block) => block.unparse_at(indent),
+            Expression::Map(map) => {
+                let arms = map
+                    .arms
+                    .iter()
+                    .map(|arm| {
+                        format!(
+                            "{}{}{} => {}",
+                            self::indent(indent + 1),
+                            arm.patterns
+                                .iter()
+                                .map(|p| p.unparse_at(indent + 1))
+                                .collect::<Vec<_>>()
+                                .join(" | "),
+                            arm.guard.as_ref().map_or(String::new(), |guard| {
+                                format!(" if {}", guard.unparse_at(indent + 1))
+                            }),
+                            arm.value.unparse_at(indent + 1)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!(
+                    "map {} {{\n{}\n{}}}",
+                    map.input.unparse_at(indent),
+                    arms,
+                    self::indent(indent)
+                )
+            }
+            Expression::FunctionCall(call) => format!(
+                "{}({})",
+                call.name,
+                call.arguments
+                    .iter()
+                    .map(|a| a.unparse_at(indent))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Expression::Array(array) => format!(
+                "[{}]",
+                array
+                    .elements
+                    .iter()
+                    .map(|e| e.unparse_at(indent))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Expression::Set(set) => format!(
+                "#{{{}}}",
+                set.elements
+                    .iter()
+                    .map(|e| e.unparse_at(indent))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Expression::Table(table) => format!(
+                "{{{}}}",
+                table
+                    .entries
+                    .iter()
+                    .map(|(key, value)| match (key, value) {
+                        // `{x}` would be a block, so one entry is never shortened
+                        (TableKey::Fixed(key), Expression::Identifier(name))
+                            if key == name && table.entries.len() > 1 =>
+                        {
+                            key.clone()
+                        }
+                        (TableKey::Fixed(key), value) => {
+                            format!("{}: {}", fmt_table_key(key), value.unparse_at(indent))
+                        }
+                        (TableKey::Computed(key), value) =>
+                            format!("[{}]: {}", key.unparse_at(indent), value.unparse_at(indent)),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Expression::If(if_expression) => {
+                let mut src = String::new();
+                for (i, (condition, statement)) in if_expression.conditionals.iter().enumerate() {
+                    if i > 0 {
+                        let (_, previous) = &if_expression.conditionals[i - 1];
+                        src.push_str(&else_separator(previous, indent));
+                        src.push_str("else ");
+                    }
+                    src.push_str(&format!(
+                        "if {}{}",
+                        condition.unparse_at(indent),
+                        unparse_branch(statement, indent)
+                    ));
+                }
+                if let Some(otherwise) = &if_expression.otherwise {
+                    let (_, last) = if_expression.conditionals.last().unwrap();
+                    src.push_str(&format!(
+                        "{}else{}",
+                        else_separator(last, indent),
+                        unparse_branch(otherwise, indent)
+                    ));
+                }
+                src
+            }
+        }
+    }
+}
+
+impl Unparse for Value {
+    fn unparse_at(&self, indent: usize) -> String {
+        match self {
+            Value::Number(value) => fmt_number(*value),
+            Value::String(value) => fmt_string_literal(value),
+            Value::Boolean(value) => value.to_string(),
+            Value::Nil => "nil".to_string(),
+            Value::ExclusiveRange(start, end) => {
+                format!("{}..{}", fmt_number(*start), fmt_number(*end))
+            }
+            Value::InclusiveRange(start, end) => {
+                format!("{}...{}", fmt_number(*start), fmt_number(*end))
+            }
+            Value::Array(array) => format!(
+                "[{}]",
+                array
+                    .elements
+                    .iter()
+                    .map(|e| e.unparse_at(indent))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Value::Table(table) => {
+                let mut keys = table.map.keys().collect::<Vec<_>>();
+                keys.sort();
+                format!(
+                    "{{{}}}",
+                    keys.into_iter()
+                        .map(|k| format!(
+                            "{}: {}",
+                            fmt_table_key(k),
+                            table.map[k].unparse_at(indent)
+                        ))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            }
+            Value::Set(set) => format!(
+                "#{{{}}}",
+                set.sorted()
+                    .into_iter()
+                    .map(|key| key.to_value().unparse_at(indent))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Value::Closure(Closure::Normal(closure)) => format!(
+                "|{}| {}",
+                fmt_parameters(&closure.arguments),
+                closure.body.unparse_at(indent)
+            ),
+            Value::Closure(Closure::Native(_)) => "|...| { NativeCode }".to_string(),
+            Value::UserData(data) => data.object.label(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn roundtrip(src: &str) -> String {
+        parse(src).unwrap().unparse()
+    }
+
+    #[test]
+    fn parentheses_only_where_needed() {
+        assert_eq!(roundtrip("(1 + 2) * 3"), "(1 + 2) * 3");
+        assert_eq!(roundtrip("1 + (2 * 3)"), "1 + 2 * 3");
+        assert_eq!(roundtrip("a - (b - c)"), "a - (b - c)");
+        assert_eq!(roundtrip("(a - b) - c"), "a - b - c");
+    }
+
+    #[test]
+    fn unparsing_keeps_literals_closures_and_functions() {
+        let src = "fn g(x) {\n  x\n}\nt := {\"a b\": 1, c: [1, 2]}\nf := |a, b = 2| a + b";
+        assert_eq!(roundtrip(src), src);
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(fmt_number(1.5), "1.5");
+        assert_eq!(fmt_number(f64::NEG_INFINITY), "-inf");
+        assert_eq!(fmt_number(f64::NAN), "nan");
+        assert_eq!(fmt_string_literal("say \"hi\""), "'say \"hi\"'");
+        assert_eq!(fmt_string_literal("it's"), "\"it's\"");
+        assert_eq!(fmt_table_key("snake_case"), "snake_case");
+        assert_eq!(fmt_table_key("two words"), "\"two words\"");
+        assert_eq!(fmt_table_key(""), "\"\"");
+    }
+}
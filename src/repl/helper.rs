@@ -0,0 +1,145 @@
+use qs::{parser::Value, runtime::Runtime};
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context,
+    Helper,
+};
+use std::{cell::RefCell, collections::BTreeSet, rc::Rc};
+
+const KEYWORDS: &[&str] = &[
+    "while", "for", "fn", "let", "if", "else", "map", "import", "in", "true", "false", "nil",
+    "nan", "inf",
+];
+
+const COMMANDS: &[&str] = &["help", "vars", "history", "ast", "load", "reset", "quit"];
+
+/// Completes identifiers from the live runtime while the user types.
+pub struct ReplHelper {
+    pub runtime: Rc<RefCell<Runtime>>,
+}
+
+impl ReplHelper {
+    fn candidates(&self, line: &str, start: usize, word: &str) -> BTreeSet<String> {
+        let before = &line[..start];
+
+        if let Some(command) = word.strip_prefix(':') {
+            return COMMANDS
+                .iter()
+                .filter(|c| c.starts_with(command))
+                .map(|c| format!(":{}", c))
+                .collect();
+        }
+
+        if let Some(before) = before.strip_suffix('.') {
+            let name_start = before
+                .rfind(|c: char| !is_ident_char(c))
+                .map(|i| i + 1)
+                .unwrap_or(0);
+            let name = &before[name_start..];
+            if name.is_empty() {
+                return BTreeSet::new();
+            }
+            return match self.runtime.borrow().storage.get(name) {
+                Value::Table(table) => table
+                    .map
+                    .keys()
+                    .filter(|k| k.starts_with(word))
+                    .cloned()
+                    .collect(),
+                _ => BTreeSet::new(),
+            };
+        }
+
+        let runtime = self.runtime.borrow();
+        runtime
+            .storage
+            .names()
+            .map(String::as_str)
+            .chain(KEYWORDS.iter().copied())
+            .filter(|name| name.starts_with(word) && *name != "_")
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let mut start = line
+            .rfind(|c: char| !is_ident_char(c))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        // a ':' at the start of the line begins a meta command
+        if line[..start].trim() == ":" {
+            start = line.find(':').unwrap();
+        }
+        let word = &line[start..];
+        let candidates = self.candidates(line, start, word);
+        Ok((start, candidates.into_iter().collect()))
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn helper(src: &str) -> ReplHelper {
+        let mut runtime = Runtime::new();
+        runtime.eval_str(src).unwrap();
+        ReplHelper {
+            runtime: Rc::new(RefCell::new(runtime)),
+        }
+    }
+
+    fn candidates(helper: &ReplHelper, line: &str) -> Vec<String> {
+        let start = line.rfind(|c: char| !is_ident_char(c)).map_or(0, |i| i + 1);
+        helper
+            .candidates(line, start, &line[start..])
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn variables_and_keywords() {
+        let helper = helper("width := 1\nwhat := 2");
+        assert_eq!(candidates(&helper, "x := wh"), ["what", "while"]);
+        assert_eq!(candidates(&helper, "wi"), ["width"]);
+    }
+
+    #[test]
+    fn table_keys() {
+        let helper = helper("point := {x: 1, xy: 2, y: 3}");
+        assert_eq!(candidates(&helper, "point.x"), ["x", "xy"]);
+        assert!(candidates(&helper, "missing.x").is_empty());
+    }
+
+    #[test]
+    fn meta_commands() {
+        let helper = helper("");
+        let found = helper
+            .candidates(":h", 0, ":h")
+            .into_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(found, [":help", ":history"]);
+    }
+}
@@ -0,0 +1,270 @@
+mod helper;
+
+use qs::{
+    parser,
+    runtime::{self, PrintOptions, Printable, Runtime, Tracking},
+};
+use helper::ReplHelper;
+use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
+use std::{cell::RefCell, env, fs::read_to_string, path::PathBuf, rc::Rc};
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+const HELP: &str = "\
+:help          show this message
+:vars          list variables in every scope
+:history <var> list values <var> was given (needs --history)
+:ast <code>    print the syntax tree of <code>
+:load <file>   run <file> in the current session
+:reset         start over with a fresh runtime
+:quit          exit (same as ctrl-d)";
+
+pub struct Repl {
+    editor: Editor<ReplHelper, DefaultHistory>,
+    runtime: Rc<RefCell<Runtime>>,
+    make_runtime: Box<dyn Fn() -> Runtime>,
+    history: Option<PathBuf>,
+}
+
+impl Repl {
+    pub fn new(make_runtime: Box<dyn Fn() -> Runtime>) -> Self {
+        // scripts print everything, results here are capped
+        runtime::set_print_options(PrintOptions {
+            width: runtime::print_options().width,
+            ..PrintOptions::INTERACTIVE
+        });
+        let runtime = Rc::new(RefCell::new(make_runtime()));
+        let mut editor = Editor::new().unwrap();
+        editor.set_helper(Some(ReplHelper {
+            runtime: runtime.clone(),
+        }));
+        let history = history_path();
+        if let Some(path) = &history {
+            let _ = editor.load_history(path);
+        }
+        Self {
+            editor,
+            runtime,
+            make_runtime,
+            history,
+        }
+    }
+
+    pub fn run(&mut self) {
+        ctrlc::set_handler(runtime::interrupt).unwrap();
+
+        while let Some(input) = self.read_input() {
+            let _ = self.editor.add_history_entry(input.as_str());
+            let input = input.trim();
+            if let Some(command) = input.strip_prefix(':') {
+                if !self.run_command(command) {
+                    break;
+                }
+            } else if !input.is_empty() {
+                self.eval(input, "<repl>");
+            }
+        }
+
+        if let Some(path) = &self.history {
+            let _ = self.editor.save_history(path);
+        }
+    }
+
+    /// Reads lines until brackets, strings and comments are closed.
+    /// Returns `None` on ctrl-d or when the terminal goes away.
+    fn read_input(&mut self) -> Option<String> {
+        let mut input = String::new();
+        loop {
+            let prompt = if input.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+            match self.editor.readline(prompt) {
+                Ok(line) => {
+                    if !input.is_empty() {
+                        input.push('\n');
+                    }
+                    input.push_str(&line);
+                    if is_complete(&input) {
+                        return Some(input);
+                    }
+                }
+                Err(ReadlineError::Interrupted) => input.clear(),
+                Err(ReadlineError::Eof) if !input.is_empty() => input.clear(),
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Returns `false` when the session should end.
+    fn run_command(&mut self, command: &str) -> bool {
+        let (name, arg) = command
+            .split_once(char::is_whitespace)
+            .map(|(name, arg)| (name, arg.trim()))
+            .unwrap_or((command, ""));
+
+        match name {
+            "help" | "h" => println!("{}", HELP),
+            "vars" => self.print_vars(),
+            "history" => self.print_history(arg),
+            "ast" => match parser::parse(arg) {
+                Ok(program) => println!("{}", serde_json::to_string_pretty(&program).unwrap()),
+                Err(e) => eprintln!("{}", e),
+            },
+            "load" => match read_to_string(arg) {
+                Ok(src) => self.eval(&src, arg),
+                Err(e) => eprintln!("can't read {}: {}", arg, e),
+            },
+            "reset" => *self.runtime.borrow_mut() = (self.make_runtime)(),
+            "quit" | "q" => return false,
+            _ => eprintln!("unknown command :{}, try :help", name),
+        }
+        true
+    }
+
+    fn eval(&mut self, src: &str, name: &str) {
+        let program = match parser::parse_named(src, name) {
+            Ok(program) => program,
+            Err(e) => return eprintln!("{}", e),
+        };
+        let res = self.runtime.borrow_mut().run(program);
+        match res {
+            Ok(value) => println!("{}", value.fmt_print()),
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+
+    fn print_vars(&self) {
+        for (depth, scope) in self.runtime.borrow().storage.scopes.iter().enumerate() {
+            let mut names = scope.names().collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                println!("[{}] {} = {}", depth, name, scope.get(name).fmt_print());
+            }
+        }
+    }
+
+    fn print_history(&self, name: &str) {
+        let history = self.runtime.borrow().history();
+        let history = history.borrow();
+        if name.is_empty() {
+            return eprintln!("usage: :history <var>");
+        }
+        if history.tracking == Tracking::Off {
+            return eprintln!("history is off, start qs with --history");
+        }
+        if !history.tracks(name) {
+            return eprintln!("{} isn't tracked", name);
+        }
+        for assignment in history.log(name) {
+            let location = match &assignment.span {
+                Some(span) => format!("{}:{}:{} ", span.source, span.line, span.column),
+                None => String::new(),
+            };
+            println!(
+                "[{}step {}] {} = {}",
+                location,
+                assignment.step,
+                name,
+                assignment.value.fmt_print()
+            );
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".qs_history"))
+}
+
+/// Whether `src` has no unclosed brackets, strings or block comments, and doesn't end with an
+/// operator, which continues the expression on the next line like in files.
+fn is_complete(src: &str) -> bool {
+    let mut depth = 0i32;
+    // `src` without comments, and with strings left empty
+    let mut code = String::new();
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '"' | '\'' => loop {
+                match chars.next() {
+                    None => return false,
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some(q) if q == c => break,
+                    Some(_) => (),
+                }
+            },
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    match chars.next() {
+                        None => return false,
+                        Some('/') if prev == '*' => break,
+                        Some(c) => prev = c,
+                    }
+                }
+                continue;
+            }
+            _ => (),
+        }
+        code.push(c);
+    }
+    let code = code.trim_end();
+    let operator = code.ends_with(|c| "+-*/%=<>&|$@.:".contains(c))
+        || code
+            .strip_suffix("in")
+            .is_some_and(|rest| !rest.ends_with(|c: char| c.is_alphanumeric() || c == '_'));
+    depth <= 0 && !operator
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_brackets_ask_for_more() {
+        assert!(is_complete("x := [1, 2]"));
+        assert!(!is_complete("fn f(a) {"));
+        assert!(!is_complete("f(1,"));
+        assert!(is_complete("fn f(a) {\n  a\n}"));
+    }
+
+    #[test]
+    fn brackets_in_strings_and_comments_dont_count() {
+        assert!(is_complete("s := \"{[(\""));
+        assert!(is_complete("s := 'a\\'{'"));
+        assert!(is_complete("x := 1 // {"));
+        assert!(is_complete("x := /* { */ 1"));
+    }
+
+    #[test]
+    fn trailing_operators_ask_for_more() {
+        assert!(!is_complete("x := 1 +"));
+        assert!(!is_complete("ok := a &&  // and"));
+        assert!(!is_complete("x :="));
+        assert!(!is_complete("2 in"));
+        assert!(is_complete("x := main"));
+        assert!(is_complete("s := \"a +\""));
+        assert!(is_complete("x := 1 + 2"));
+    }
+
+    #[test]
+    fn unclosed_strings_and_comments_ask_for_more() {
+        assert!(!is_complete("s := \"abc"));
+        assert!(!is_complete("x := /* note"));
+    }
+}
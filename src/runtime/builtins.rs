@@ -0,0 +1,144 @@
+use super::{print_options, set_print_options, type_name, Error, Printable, Runtime};
+use crate::parser::*;
+use similar::{ChangeTag, TextDiff};
+use std::collections::HashSet;
+
+/// Registers the functions of the language itself, which `Runtime::with_std` starts with.
+pub(super) fn register(runtime: &mut Runtime) {
+    // print options are shared by every runtime of the process, see `set_print_options`
+    runtime.register_fn("print_options", |v: Vec<Value>| {
+        let mut options = print_options();
+        if let Some(Value::Table(table)) = v.first() {
+            let limit = |value: &Value| match value {
+                Value::Number(n) if *n >= 0. => Some(*n as usize),
+                _ => None,
+            };
+            for (key, value) in &table.map {
+                match key.as_str() {
+                    "width" => options.width = limit(value),
+                    "items" => options.items = limit(value),
+                    "depth" => options.depth = limit(value),
+                    _ => (),
+                }
+            }
+            set_print_options(options);
+        }
+        let limit = |n: Option<usize>| n.map(|n| Value::Number(n as f64)).unwrap_or(Value::Nil);
+        Value::Table(ValuesTable {
+            map: [
+                ("width".to_string(), limit(options.width)),
+                ("items".to_string(), limit(options.items)),
+                ("depth".to_string(), limit(options.depth)),
+            ]
+            .into(),
+        })
+    });
+
+    let history = runtime.history();
+    runtime.register_fn("history", move |v: Vec<Value>| match v.first() {
+        Some(Value::String(name)) => history.borrow().log_value(name),
+        _ => Value::Nil,
+    });
+
+    runtime.register_try_fn("set", |v: Vec<Value>| match v.into_iter().next() {
+        Some(Value::Array(array)) => {
+            let mut elements = HashSet::new();
+            for value in &array.elements {
+                elements.insert(Key::new(value).ok_or(Error::Type {
+                    expected: "number, string, boolean, nil or array",
+                    found: type_name(value),
+                })?);
+            }
+            Ok(Value::Set(ValuesSet { elements }))
+        }
+        Some(Value::Set(set)) => Ok(Value::Set(set)),
+        _ => Ok(Value::Nil),
+    });
+
+    runtime.register_try_fn("assert", |v: Vec<Value>| {
+        let mut v = v.into_iter();
+        if v.next() == Some(Value::Boolean(true)) {
+            return Ok(Value::Nil);
+        }
+        Err(Error::Assertion(match v.next() {
+            Some(Value::String(message)) => message,
+            Some(message) => message.fmt_print(),
+            None => "condition is not true".to_string(),
+        }))
+    });
+
+    runtime.register_try_fn("assert_eq", |v: Vec<Value>| {
+        let mut v = v.into_iter();
+        let left = v.next().unwrap_or(Value::Nil);
+        let right = v.next().unwrap_or(Value::Nil);
+        if left == right {
+            return Ok(Value::Nil);
+        }
+        Err(Error::Assertion(format!(
+            "values differ\n{}",
+            diff(&left.fmt_debug(), &right.fmt_debug())
+        )))
+    });
+}
+
+/// Line by line difference between two values shown by `fmt_debug`, `-` lines are from `left`.
+pub fn diff(left: &str, right: &str) -> String {
+    TextDiff::from_lines(left, right)
+        .iter_all_changes()
+        .map(|change| {
+            let sign = match change.tag() {
+                ChangeTag::Delete => "-",
+                ChangeTag::Insert => "+",
+                ChangeTag::Equal => " ",
+            };
+            format!("{}{}", sign, change.value().trim_end_matches('\n'))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(src: &str) -> super::super::Result<Value> {
+        Runtime::with_std().eval_str(src)
+    }
+
+    #[test]
+    fn diff_marks_lines_of_each_side() {
+        assert_eq!(
+            diff("[\n  1,\n  2\n]", "[\n  1,\n  3\n]"),
+            " [\n   1,\n-  2\n+  3\n ]"
+        );
+        assert_eq!(diff("1", "1"), " 1");
+    }
+
+    #[test]
+    fn assertions() {
+        assert_eq!(eval("assert(1 == 1)"), Ok(Value::Nil));
+        assert_eq!(
+            eval("assert(false, \"nope\")"),
+            Err(Error::Assertion("nope".to_string()))
+        );
+        assert_eq!(
+            eval("assert_eq(1, 2)"),
+            Err(Error::Assertion(
+                "values differ\n-number 1\n+number 2".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn sets_from_arrays() {
+        assert_eq!(eval("set([1, 1, 2]) == #{1, 2}"), Ok(Value::Boolean(true)));
+        assert!(matches!(eval("set([{}])"), Err(Error::Type { .. })));
+    }
+
+    #[test]
+    fn only_with_std() {
+        let mut runtime = Runtime::new();
+        assert_eq!(runtime.get_global("assert"), None);
+        assert!(Runtime::with_std().get_global("assert").is_some());
+    }
+}
@@ -0,0 +1,223 @@
+use super::{type_name, Error, Result};
+use crate::parser::*;
+use std::collections::HashMap;
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Number(value.into())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Nil
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Nil)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(value: Vec<T>) -> Self {
+        Value::Array(ValuesArray {
+            elements: value.into_iter().map(Into::into).collect(),
+        })
+    }
+}
+
+impl<T: Into<Value>> From<HashMap<String, T>> for Value {
+    fn from(value: HashMap<String, T>) -> Self {
+        Value::Table(ValuesTable {
+            map: value.into_iter().map(|(k, v)| (k, v.into())).collect(),
+        })
+    }
+}
+
+fn mismatch(expected: &'static str, found: &Value) -> Error {
+    Error::Type {
+        expected,
+        found: type_name(found),
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Number(value) => Ok(value),
+            _ => Err(mismatch("number", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Boolean(value) => Ok(value),
+            _ => Err(mismatch("boolean", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::String(value) => Ok(value),
+            _ => Err(mismatch("string", &value)),
+        }
+    }
+}
+
+impl<T: TryFrom<Value, Error = Error>> TryFrom<Value> for Vec<T> {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Array(array) => array.elements.into_iter().map(T::try_from).collect(),
+            _ => Err(mismatch("array", &value)),
+        }
+    }
+}
+
+impl<T: TryFrom<Value, Error = Error>> TryFrom<Value> for HashMap<String, T> {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Table(table) => table
+                .map
+                .into_iter()
+                .map(|(k, v)| Ok((k, T::try_from(v)?)))
+                .collect(),
+            _ => Err(mismatch("table", &value)),
+        }
+    }
+}
+
+impl<T: TryFrom<Value, Error = Error>> TryFrom<Value> for Option<T> {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::try_from(value).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scalars() {
+        assert_eq!(f64::try_from(Value::Number(1.5)), Ok(1.5));
+        assert_eq!(bool::try_from(Value::Boolean(true)), Ok(true));
+        assert_eq!(String::try_from(Value::from("a")), Ok("a".to_string()));
+    }
+
+    #[test]
+    fn scalar_mismatches() {
+        assert_eq!(
+            f64::try_from(Value::from("1")),
+            Err(Error::Type {
+                expected: "number",
+                found: "string",
+            })
+        );
+        assert_eq!(
+            bool::try_from(Value::Nil),
+            Err(Error::Type {
+                expected: "boolean",
+                found: "nil",
+            })
+        );
+        assert_eq!(
+            String::try_from(Value::Number(1.0)),
+            Err(Error::Type {
+                expected: "string",
+                found: "number",
+            })
+        );
+    }
+
+    #[test]
+    fn collections() {
+        let array = Value::from(vec![1, 2]);
+        assert_eq!(Vec::<f64>::try_from(array), Ok(vec![1.0, 2.0]));
+        let table = Value::from(HashMap::from([("a".to_string(), true)]));
+        assert_eq!(
+            HashMap::<String, bool>::try_from(table),
+            Ok(HashMap::from([("a".to_string(), true)]))
+        );
+        assert_eq!(Option::<f64>::try_from(Value::Nil), Ok(None));
+        assert_eq!(Option::<f64>::try_from(Value::Number(2.0)), Ok(Some(2.0)));
+    }
+
+    #[test]
+    fn collection_mismatches() {
+        assert_eq!(
+            Vec::<f64>::try_from(Value::Nil),
+            Err(Error::Type {
+                expected: "array",
+                found: "nil",
+            })
+        );
+        // the first element of the wrong type fails the whole array
+        let mixed = Value::from(vec![Value::Number(1.0), Value::Boolean(false)]);
+        assert_eq!(
+            Vec::<f64>::try_from(mixed),
+            Err(Error::Type {
+                expected: "number",
+                found: "boolean",
+            })
+        );
+        assert_eq!(
+            HashMap::<String, f64>::try_from(Value::from(vec![1])),
+            Err(Error::Type {
+                expected: "table",
+                found: "array",
+            })
+        );
+        // only nil is `None`, anything else has to convert
+        assert_eq!(
+            Option::<f64>::try_from(Value::from("x")),
+            Err(Error::Type {
+                expected: "number",
+                found: "string",
+            })
+        );
+    }
+}
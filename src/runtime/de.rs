@@ -0,0 +1,463 @@
+use super::{type_name, Error, Result};
+use crate::parser::*;
+use serde::{
+    de::{
+        self,
+        value::{MapDeserializer, SeqDeserializer},
+        DeserializeOwned, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize,
+};
+use std::{collections::HashMap, fmt};
+
+/// Converts a `Value` into any `Deserialize` type, tables fill in structs and maps.
+pub fn from_value<T>(value: Value) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(value)
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Convert(msg.to_string())
+    }
+}
+
+impl IntoDeserializer<'_, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            // whole numbers are handed out as integers so integer fields accept them
+            Value::Number(n) if n.fract() == 0. && n.abs() < 2f64.powi(53) => {
+                if n < 0. {
+                    visitor.visit_i64(n as i64)
+                } else {
+                    visitor.visit_u64(n as u64)
+                }
+            }
+            Value::Number(n) => visitor.visit_f64(n),
+            Value::String(s) => visitor.visit_string(s),
+            Value::Boolean(b) => visitor.visit_bool(b),
+            Value::Nil => visitor.visit_unit(),
+            Value::Array(array) => {
+                let mut seq = SeqDeserializer::new(array.elements.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Set(set) => {
+                let elements = set.sorted().into_iter().map(|key| key.to_value());
+                let elements = elements.collect::<Vec<_>>();
+                let mut seq = SeqDeserializer::new(elements.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Table(table) => {
+                let mut map = MapDeserializer::new(table.map.into_iter().map(|(k, v)| (Key(k), v)));
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            Value::ExclusiveRange(start, end) | Value::InclusiveRange(start, end) => {
+                let mut seq = SeqDeserializer::new([start, end].into_iter().map(Value::Number));
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Closure(_) => Err(Error::Convert("closures can't be converted".to_string())),
+            Value::UserData(_) => Err(Error::Convert("host objects can't be converted".to_string())),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Nil => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        // unit variants are plain strings, the others `{variant: value}`
+        let (variant, value) = match self {
+            Value::String(variant) => (variant, None),
+            Value::Table(table) if table.map.len() == 1 => {
+                let (variant, value) = table.map.into_iter().next().unwrap();
+                (variant, Some(value))
+            }
+            value => {
+                return Err(Error::Type {
+                    expected: "string or table with one key",
+                    found: type_name(&value),
+                })
+            }
+        };
+        visitor.visit_enum(EnumDeserializer { variant, value })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// A table key, parsed again when a map has number or boolean keys.
+struct Key(String);
+
+impl IntoDeserializer<'_, Error> for Key {
+    type Deserializer = Key;
+
+    fn into_deserializer(self) -> Key {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident),*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+            where
+                V: Visitor<'de>,
+            {
+                match self.0.parse() {
+                    Ok(key) => visitor.$visit(key),
+                    Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(&self.0), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Key {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_string(self.0)
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf option unit unit_struct newtype_struct
+        seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct EnumDeserializer {
+    variant: String,
+    value: Option<Value>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, VariantDeserializer)>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(Value::String(self.variant))?;
+        Ok((variant, VariantDeserializer { value: self.value }))
+    }
+}
+
+struct VariantDeserializer {
+    value: Option<Value>,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.value {
+            None | Some(Value::Nil) => Ok(()),
+            Some(value) => Err(Error::Type {
+                expected: "nil",
+                found: type_name(&value),
+            }),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.value.unwrap_or(Value::Nil))
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_seq(self.value.unwrap_or(Value::Nil), visitor)
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_map(self.value.unwrap_or(Value::Nil), visitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any qs value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Value, E> {
+        Ok(Value::Number(v as f64))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E> {
+        Ok(Value::Number(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> std::result::Result<Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut elements = vec![];
+        while let Some(element) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(Value::Array(ValuesArray { elements }))
+    }
+
+    fn visit_map<A>(self, mut access: A) -> std::result::Result<Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut map = HashMap::new();
+        while let Some((key, value)) = access.next_entry::<String, Value>()? {
+            map.insert(key, value);
+        }
+        Ok(Value::Table(ValuesTable { map }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::to_value;
+    use serde::{Deserialize, Serialize};
+    use std::fmt::Debug;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        x: f64,
+        y: f64,
+        label: Option<String>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Line(Point, Point),
+        Rect { width: f64, height: f64 },
+    }
+
+    fn round_trip<T>(value: T)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        let converted = to_value(&value).unwrap();
+        assert_eq!(from_value::<T>(converted).unwrap(), value);
+    }
+
+    #[test]
+    fn structs() {
+        round_trip(Point {
+            x: 1.0,
+            y: -2.5,
+            label: Some("a".to_string()),
+        });
+        let point = to_value(&Point {
+            x: 1.0,
+            y: 2.0,
+            label: None,
+        })
+        .unwrap();
+        let Value::Table(table) = point else {
+            panic!("{:?}", point)
+        };
+        assert_eq!(table.map["x"], Value::Number(1.0));
+        assert_eq!(table.map["label"], Value::Nil);
+    }
+
+    #[test]
+    fn enums() {
+        round_trip(Shape::Empty);
+        round_trip(Shape::Circle(2.0));
+        round_trip(Shape::Line(
+            Point {
+                x: 0.0,
+                y: 0.0,
+                label: None,
+            },
+            Point {
+                x: 1.0,
+                y: 1.0,
+                label: None,
+            },
+        ));
+        round_trip(Shape::Rect {
+            width: 3.0,
+            height: 4.0,
+        });
+        // unit variants are plain strings
+        assert_eq!(
+            to_value(&Shape::Empty).unwrap(),
+            Value::String("Empty".to_string())
+        );
+    }
+
+    #[test]
+    fn options() {
+        round_trip(Some(1.0));
+        round_trip(None::<f64>);
+        round_trip(Some(Some(true)));
+    }
+
+    #[test]
+    fn maps() {
+        let map = HashMap::from([("a".to_string(), 1.0), ("b".to_string(), 2.0)]);
+        round_trip(map);
+        let nested = HashMap::from([("inner".to_string(), HashMap::from([("x".to_string(), 1)]))]);
+        round_trip(nested);
+    }
+
+    #[test]
+    fn nested_vecs() {
+        round_trip(vec![vec![1, 2], vec![], vec![3]]);
+        round_trip(vec![vec![vec!["a".to_string()]]]);
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let res = from_value::<Point>(Value::String("x".to_string()));
+        assert_eq!(
+            res,
+            Err(Error::Convert(
+                "invalid type: string \"x\", expected struct Point".to_string()
+            ))
+        );
+        let res = from_value::<Shape>(Value::Number(1.0));
+        assert_eq!(
+            res,
+            Err(Error::Type {
+                expected: "string or table with one key",
+                found: "number",
+            })
+        );
+    }
+
+    #[test]
+    fn closures_are_refused() {
+        let closure = crate::runtime::Runtime::new().eval_str("|x| x").unwrap();
+        let table = Value::Table(ValuesTable {
+            map: [("f".to_string(), closure.clone())].into(),
+        });
+        assert_eq!(
+            to_value(&table),
+            Err(Error::Convert("closures can't be serialized".to_string()))
+        );
+        assert!(serde_json::to_string(&closure).is_err());
+        assert_eq!(
+            from_value::<Value>(closure),
+            Err(Error::Convert("closures can't be converted".to_string()))
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use crate::parser::DebugInfo;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+    time::Instant,
+};
+
+static DEBUG_LEVEL: AtomicUsize = AtomicUsize::new(1);
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Only `??` with at most `level + 1` question marks are printed, 0 silences them all. The level
+/// is the same for every runtime of the process.
+pub fn set_debug_level(level: usize) {
+    DEBUG_LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// Starts the clock `??` timestamps are relative to, the first `??` starts it otherwise.
+pub fn start_clock() {
+    START.get_or_init(Instant::now);
+}
+
+/// Prints `[file:line:col +1.2ms] text = value` to stderr if `info` is within the debug level.
+pub fn debug_print(info: &DebugInfo, value: &str) {
+    if info.level > DEBUG_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    let elapsed = START.get_or_init(Instant::now).elapsed();
+    eprintln!(
+        "[{}:{}:{} +{:.1}ms] {} = {}",
+        info.source,
+        info.line,
+        info.column,
+        elapsed.as_secs_f64() * 1000.,
+        info.text,
+        value
+    );
+}
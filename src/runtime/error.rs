@@ -0,0 +1,46 @@
+use super::Limit;
+use crate::parser::ParseError;
+use thiserror::Error as ThisError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq, ThisError)]
+pub enum Error {
+    #[error("{0}")]
+    Parse(#[from] ParseError),
+    #[error("interrupted")]
+    Interrupted,
+    #[error("{0} limit exceeded")]
+    LimitExceeded(Limit),
+    #[error("expected {expected}, found {found}")]
+    Type {
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("{0}")]
+    Import(String),
+    /// Strict mode only, like the four below.
+    #[error("{0} is not defined")]
+    Undefined(String),
+    #[error("assignment to undeclared variable {0}")]
+    Undeclared(String),
+    #[error("expected {expected} arguments, found {found}")]
+    Arity { expected: usize, found: usize },
+    #[error("can't apply {op} to {operands}")]
+    Operands { op: &'static str, operands: String },
+    /// A value that doesn't fit the pattern of `:=` or a parameter.
+    #[error("can't destructure {found} with {pattern}")]
+    Destructure {
+        pattern: String,
+        found: &'static str,
+    },
+    /// Raised by `to_value` and `from_value`.
+    #[error("{0}")]
+    Convert(String),
+    /// Raised by host functions.
+    #[error("{0}")]
+    Native(String),
+    /// Raised by `assert` and `assert_eq`.
+    #[error("assertion failed: {0}")]
+    Assertion(String),
+}
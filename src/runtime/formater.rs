@@ -1,53 +1,344 @@
 #![allow(unused)]
 
 use crate::parser::*;
+use std::borrow::Cow;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+static COLORS: AtomicBool = AtomicBool::new(false);
+
+/// Limits applied by `fmt_print`, `None` means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrintOptions {
+    /// Break arrays and tables over several lines when they don't fit.
+    pub width: Option<usize>,
+    /// Elements shown per array or table before `... N more`.
+    pub items: Option<usize>,
+    /// Nesting shown before values are replaced with `[...]` and `{...}`.
+    pub depth: Option<usize>,
+}
+
+impl PrintOptions {
+    /// What scripts print with until they call `print_options`.
+    pub const UNLIMITED: PrintOptions = PrintOptions {
+        width: None,
+        items: None,
+        depth: None,
+    };
+    /// Caps for values only glanced at, REPL results and `??`.
+    pub const INTERACTIVE: PrintOptions = PrintOptions {
+        width: None,
+        items: Some(100),
+        depth: Some(32),
+    };
+}
+
+static PRINT_OPTIONS: Mutex<PrintOptions> = Mutex::new(PrintOptions::UNLIMITED);
+
+/// The options `fmt_debug` prints with, shared by every runtime of the process.
+pub fn print_options() -> PrintOptions {
+    *PRINT_OPTIONS.lock().unwrap()
+}
+
+/// Changes the print options of every runtime of the process, as `print_options(...)` does.
+pub fn set_print_options(options: PrintOptions) {
+    *PRINT_OPTIONS.lock().unwrap() = options;
+}
+
+/// Turns colored output on or off for the whole process.
+pub fn set_colors(enabled: bool) {
+    COLORS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn colors_enabled() -> bool {
+    COLORS.load(Ordering::Relaxed)
+}
+
+const NUMBER: &str = "33";
+const STRING: &str = "32";
+const KEYWORD: &str = "35";
+const NIL: &str = "90";
+const CLOSURE: &str = "36";
+const TYPE: &str = "34";
+
+fn paint(color: &str, text: String) -> String {
+    if colors_enabled() {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text
+    }
+}
 
 pub trait Printable {
     fn fmt_print(&self) -> String;
     fn fmt_debug(&self) -> String;
 }
 
+/// Name of the value's type as shown to users.
+pub fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Table(_) => "table",
+        Value::ExclusiveRange(..) | Value::InclusiveRange(..) => "range",
+        Value::Array(_) => "array",
+        Value::Set(_) => "set",
+        Value::Closure(Closure::Normal(_)) => "closure",
+        Value::Closure(Closure::Native(_)) => "native",
+        Value::UserData(_) => "userdata",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Boolean(_) => "boolean",
+        Value::Nil => "nil",
+    }
+}
+
+fn sorted_entries(table: &ValuesTable) -> Vec<(&String, &Value)> {
+    let mut entries = table.map.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
 impl Printable for Value {
     fn fmt_print(&self) -> String {
-        match self {
-            Value::Table(values_table) => {
-                format!(
-                    "{{{}}}",
-                    values_table
-                        .map
-                        .iter()
-                        .map(|(key, value)| format!("{}: {}", key, value.fmt_print()))
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                )
-            }
-            Value::ExclusiveRange(start, end) => format!("{}..{}", start, end),
-            Value::InclusiveRange(start, end) => format!("{}...{}", start, end),
-            Value::Array(values_array) => {
-                format!(
-                    "[{}]",
-                    values_array
-                        .elements
-                        .iter()
-                        .map(|value| value.fmt_print())
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                )
-            }
-            Value::Number(value) => value.to_string(),
-            Value::String(value) => format!("\"{}\"", value),
-            Value::Boolean(value) => value.to_string(),
-            Value::Nil => "nil".to_string(),
-            Value::Closure(closure) => match closure {
+        pretty(self, &print_options(), 0, 0)
+    }
+
+    fn fmt_debug(&self) -> String {
+        fmt_debug_with(self, &PrintOptions::UNLIMITED)
+    }
+}
+
+/// `fmt_debug` with the item and depth caps of `options`, its width is ignored.
+pub fn fmt_debug_with(value: &Value, options: &PrintOptions) -> String {
+    let name = match value {
+        Value::Array(array) => format!("array({})", array.elements.len()),
+        Value::Table(table) => format!("table({})", table.map.len()),
+        Value::Set(set) => format!("set({})", set.elements.len()),
+        Value::Nil => return debug_value(value, options, 0),
+        _ => type_name(value).to_string(),
+    };
+    format!("{} {}", paint(TYPE, name), debug_value(value, options, 0))
+}
+
+fn fmt_scalar(value: &Value) -> String {
+    match value {
+        Value::ExclusiveRange(start, end) => paint(
+            NUMBER,
+            format!("{}..{}", fmt_number(*start), fmt_number(*end)),
+        ),
+        Value::InclusiveRange(start, end) => paint(
+            NUMBER,
+            format!("{}...{}", fmt_number(*start), fmt_number(*end)),
+        ),
+        Value::Number(value) => paint(NUMBER, fmt_number(*value)),
+        Value::String(value) => paint(STRING, format!("\"{}\"", value)),
+        Value::Boolean(value) => paint(KEYWORD, value.to_string()),
+        Value::Nil => paint(NIL, "nil".to_string()),
+        Value::Closure(closure) => paint(
+            CLOSURE,
+            match closure {
                 Closure::Normal(normal_closure) => {
-                    format!("|{}| {{ ... }}", normal_closure.arguments.join(", "))
+                    format!("|{}| {{ ... }}", fmt_parameters(&normal_closure.arguments))
                 }
                 Closure::Native(native_closure) => "|...| { NativeCode }".to_string(),
             },
+        ),
+        Value::UserData(data) => paint(CLOSURE, data.object.label()),
+        Value::Array(_) | Value::Table(_) | Value::Set(_) => unreachable!(),
+    }
+}
+
+/// An array or set element, or a table entry with its key.
+type Child<'a> = (Option<String>, Cow<'a, Value>);
+
+/// Elements of an array, set or table as they're printed, `None` for other values.
+fn children(value: &Value) -> Option<(&'static str, &'static str, Vec<Child<'_>>)> {
+    match value {
+        Value::Array(array) => Some((
+            "[",
+            "]",
+            array
+                .elements
+                .iter()
+                .map(|value| (None, Cow::Borrowed(value)))
+                .collect(),
+        )),
+        Value::Set(set) => Some((
+            "#{",
+            "}",
+            set.sorted()
+                .into_iter()
+                .map(|key| (None, Cow::Owned(key.to_value())))
+                .collect(),
+        )),
+        Value::Table(table) => Some((
+            "{",
+            "}",
+            sorted_entries(table)
+                .into_iter()
+                .map(|(key, value)| (Some(fmt_table_key(key)), Cow::Borrowed(value)))
+                .collect(),
+        )),
+        _ => None,
+    }
+}
+
+/// Prints `value` on one line if it fits in the remaining width, one element per line otherwise.
+/// `column` is where the value starts on the current line, `depth` how deeply it's nested.
+fn pretty(value: &Value, options: &PrintOptions, column: usize, depth: usize) -> String {
+    let Some((open, close, elements)) = children(value) else {
+        return fmt_scalar(value);
+    };
+    if elements.is_empty() {
+        return format!("{}{}", open, close);
+    }
+    if options.depth.is_some_and(|max| depth >= max) {
+        return format!("{}...{}", open, close);
+    }
+
+    let total = elements.len();
+    let shown = options.items.map_or(total, |max| max.min(total));
+    let elided = (shown < total).then(|| format!("... {} more", total - shown));
+
+    let flat = elements[..shown]
+        .iter()
+        .map(|(key, value)| match key {
+            Some(key) => format!("{}: {}", key, pretty(value, options, usize::MAX, depth + 1)),
+            None => pretty(value, options, usize::MAX, depth + 1),
+        })
+        .chain(elided.clone())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let flat = format!("{}{}{}", open, flat, close);
+    // column is usize::MAX while measuring, nested values are then always flat
+    let fits = match options.width {
+        Some(width) => column == usize::MAX || column + visible_len(&flat) <= width,
+        None => true,
+    };
+    if fits {
+        return flat;
+    }
+
+    let indent = " ".repeat(column + 2);
+    let lines = elements[..shown]
+        .iter()
+        .map(|(key, value)| match key {
+            Some(key) => format!(
+                "{}{}: {}",
+                indent,
+                key,
+                pretty(value, options, column + 4 + key.len(), depth + 1)
+            ),
+            None => format!("{}{}", indent, pretty(value, options, column + 2, depth + 1)),
+        })
+        .chain(elided.map(|elided| format!("{}{}", indent, elided)))
+        .collect::<Vec<_>>()
+        .join(",\n");
+    format!("{}\n{}\n{}{}", open, lines, " ".repeat(column), close)
+}
+
+/// Length of `text` on screen, ignoring color escapes.
+fn visible_len(text: &str) -> usize {
+    let mut len = 0;
+    let mut escape = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => escape = true,
+            'm' if escape => escape = false,
+            _ if escape => (),
+            _ => len += 1,
         }
     }
+    len
+}
 
-    fn fmt_debug(&self) -> String {
-        self.fmt_print()
+/// Like `fmt_print`, but unambiguous: strings are escaped and closures show their source.
+fn debug_value(value: &Value, options: &PrintOptions, depth: usize) -> String {
+    let Some((open, close, elements)) = children(value) else {
+        return match value {
+            Value::String(value) => paint(STRING, format!("\"{}\"", value.escape_debug())),
+            Value::Closure(_) => paint(CLOSURE, value.unparse()),
+            _ => fmt_scalar(value),
+        };
+    };
+    if !elements.is_empty() && options.depth.is_some_and(|max| depth >= max) {
+        return format!("{}...{}", open, close);
+    }
+    let total = elements.len();
+    let shown = options.items.map_or(total, |max| max.min(total));
+    let elided = (shown < total).then(|| format!("... {} more", total - shown));
+    let elements = elements[..shown]
+        .iter()
+        .map(|(key, value)| match key {
+            Some(key) => format!("{}: {}", key, debug_value(value, options, depth + 1)),
+            None => debug_value(value, options, depth + 1),
+        })
+        .chain(elided)
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{}{}{}", open, elements, close)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(count: i32) -> Value {
+        Value::from((0..count).collect::<Vec<_>>())
+    }
+
+    fn table(entries: &[(&str, Value)]) -> Value {
+        Value::Table(ValuesTable {
+            map: entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn print_shows_values_plainly() {
+        assert_eq!(Value::Number(1.0).fmt_print(), "1");
+        assert_eq!(Value::Number(1.5).fmt_print(), "1.5");
+        assert_eq!(Value::Nil.fmt_print(), "nil");
+        let point = table(&[("y", Value::Number(2.0)), ("x", Value::from("a"))]);
+        assert_eq!(point.fmt_print(), "{x: \"a\", y: 2}");
+    }
+
+    #[test]
+    fn debug_names_the_type() {
+        assert_eq!(Value::Number(1.0).fmt_debug(), "number 1");
+        assert_eq!(Value::Nil.fmt_debug(), "nil");
+        assert_eq!(Value::from("a\"b\n").fmt_debug(), "string \"a\\\"b\\n\"");
+        let array = Value::from(vec![Value::Boolean(true), Value::Nil]);
+        assert_eq!(array.fmt_debug(), "array(2) [true, nil]");
+        let point = table(&[("y", Value::Number(2.0)), ("x", Value::Number(1.0))]);
+        assert_eq!(point.fmt_debug(), "table(2) {x: 1, y: 2}");
+    }
+
+    #[test]
+    fn scripts_print_everything_by_default() {
+        let text = pretty(&numbers(150), &PrintOptions::UNLIMITED, 0, 0);
+        assert!(text.contains("149"));
+        assert!(!text.contains("more"));
+    }
+
+    #[test]
+    fn interactive_caps_elide_the_rest() {
+        let text = pretty(&numbers(150), &PrintOptions::INTERACTIVE, 0, 0);
+        assert!(text.ends_with("... 50 more]"), "{}", text);
+        let text = fmt_debug_with(&numbers(150), &PrintOptions::INTERACTIVE);
+        assert!(text.ends_with("... 50 more]"), "{}", text);
+    }
+
+    #[test]
+    fn depth_cap_hides_nested_values() {
+        let nested = Value::from(vec![vec![vec![1]]]);
+        let options = PrintOptions {
+            depth: Some(1),
+            ..PrintOptions::UNLIMITED
+        };
+        assert!(fmt_debug_with(&nested, &options).ends_with("[[...]]"));
     }
 }
@@ -0,0 +1,150 @@
+use crate::parser::*;
+use std::collections::{HashMap, HashSet};
+
+/// Variables whose assignments `History` records.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Tracking {
+    #[default]
+    Off,
+    All,
+    Only(HashSet<String>),
+}
+
+/// One value a variable was given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub value: Value,
+    /// `None` for function definitions, arguments have the span of the statement making the call.
+    pub span: Option<Span>,
+    /// Steps the runtime had taken, see `Budget::total_steps`.
+    pub step: u64,
+}
+
+/// Log of assignments, opt-in as every value assigned is kept.
+#[derive(Debug, Default)]
+pub struct History {
+    pub tracking: Tracking,
+    log: HashMap<String, Vec<Assignment>>,
+    /// Span of the statement being run, every name it binds is recorded with it.
+    pub(super) span: Option<Span>,
+}
+
+impl History {
+    pub fn tracks(&self, name: &str) -> bool {
+        match &self.tracking {
+            Tracking::Off => false,
+            // _ changes after every statement
+            Tracking::All => name != "_",
+            Tracking::Only(names) => names.contains(name),
+        }
+    }
+
+    pub fn record(&mut self, name: &str, assignment: Assignment) {
+        self.log
+            .entry(name.to_string())
+            .or_default()
+            .push(assignment);
+    }
+
+    /// Assignments to `name`, oldest first.
+    pub fn log(&self, name: &str) -> &[Assignment] {
+        self.log.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The log of `name` as an array of `{value, step, source, line, column}` tables.
+    pub fn log_value(&self, name: &str) -> Value {
+        let entries = self.log(name).iter().map(|assignment| {
+            let span = assignment.span.as_ref();
+            HashMap::from([
+                ("value".to_string(), assignment.value.clone()),
+                ("step".to_string(), Value::Number(assignment.step as f64)),
+                ("source".to_string(), span.map(|s| s.source.clone()).into()),
+                ("line".to_string(), span.map(|s| s.line as f64).into()),
+                ("column".to_string(), span.map(|s| s.column as f64).into()),
+            ])
+            .into()
+        });
+        Value::Array(ValuesArray {
+            elements: entries.collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Runtime;
+
+    fn lines(history: &History, name: &str) -> Vec<Option<usize>> {
+        history
+            .log(name)
+            .iter()
+            .map(|assignment| assignment.span.as_ref().map(|span| span.line))
+            .collect()
+    }
+
+    #[test]
+    fn every_binding_has_a_span() {
+        let mut runtime = Runtime::new();
+        runtime.set_history(Tracking::All);
+        let src = "fn f(x, [y, z]) {\n  x + y\n}\n[a, b] := [1, 2]\nc := f(3, [4, 5])\n";
+        runtime.eval_str(src).unwrap();
+        let history = runtime.history();
+        let history = history.borrow();
+        assert_eq!(lines(&history, "a"), [Some(4)]);
+        assert_eq!(lines(&history, "b"), [Some(4)]);
+        // arguments are recorded where the call is
+        assert_eq!(lines(&history, "x"), [Some(5)]);
+        assert_eq!(lines(&history, "z"), [Some(5)]);
+        assert_eq!(lines(&history, "c"), [Some(5)]);
+        assert_eq!(lines(&history, "f"), [None]);
+    }
+
+    #[test]
+    fn only_tracked_names() {
+        let mut runtime = Runtime::new();
+        runtime.set_history(Tracking::Only(HashSet::from(["a".to_string()])));
+        runtime.eval_str("a := 1\nb := 2\na = 3").unwrap();
+        let history = runtime.history();
+        let history = history.borrow();
+        let values = history
+            .log("a")
+            .iter()
+            .map(|assignment| assignment.value.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(values, [Value::Number(1.0), Value::Number(3.0)]);
+        assert!(history.log("b").is_empty());
+    }
+
+    #[test]
+    fn off_by_default() {
+        let mut runtime = Runtime::new();
+        runtime.eval_str("a := 1\na = 2").unwrap();
+        assert!(runtime.history().borrow().log("a").is_empty());
+    }
+
+    #[test]
+    fn log_as_a_value() {
+        let mut runtime = Runtime::new();
+        runtime.set_history(Tracking::All);
+        runtime.eval_str("a := 1\na = a + 1").unwrap();
+        let history = runtime.history();
+        let history = history.borrow();
+        assert!(history.log("_").is_empty());
+        let steps = history.log("a").iter().map(|a| a.step).collect::<Vec<_>>();
+        assert!(steps[0] < steps[1], "{:?}", steps);
+        let Value::Array(log) = history.log_value("a") else {
+            panic!("the log is an array");
+        };
+        let Value::Table(last) = &log.elements[1] else {
+            panic!("entries are tables");
+        };
+        assert_eq!(last.map["value"], Value::Number(2.0));
+        assert_eq!(last.map["line"], Value::Number(2.0));
+        assert_eq!(last.map["column"], Value::Number(1.0));
+        assert_eq!(
+            history.log_value("b"),
+            Value::Array(ValuesArray { elements: vec![] })
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use super::{eval_body, Evaluate, Result, Storage};
+use crate::parser::*;
+
+/// Called before each statement of a program or block, see `Runtime::set_hook`.
+pub trait Hook {
+    /// An `Err` stops the run with that error.
+    fn before(&mut self, span: &Span, storage: &mut Storage) -> Result<()>;
+}
+
+/// A named call in progress, recorded while a hook is installed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub name: String,
+    /// Index of the call's scope in `Storage::scopes`.
+    pub scope: usize,
+}
+
+/// Parses and evaluates `src` in the current scope of `storage`, for hooks looking into a
+/// paused run. Unlike `Runtime::run`, `_` is left alone.
+pub fn eval_in(storage: &mut Storage, src: &str) -> Result<Value> {
+    let program = parse_named(src, "<eval>")?;
+    let (scopes, frames) = (storage.scopes.len(), storage.frames.len());
+    let res = eval_body(&program.functions, &[], &[], storage).and_then(|_| {
+        program
+            .statements
+            .iter()
+            .try_fold(Value::Nil, |_, statement| statement.eval(storage))
+    });
+    storage.scopes.truncate(scopes);
+    storage.frames.truncate(frames);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Error, Runtime};
+    use std::{cell::RefCell, rc::Rc};
+
+    /// A line, the calls in progress and the value of `x` there.
+    type Stop = (usize, Vec<String>, Value);
+
+    /// Records each statement it's called on.
+    struct Recorder {
+        seen: Rc<RefCell<Vec<Stop>>>,
+        stop_at: Option<usize>,
+    }
+
+    impl Hook for Recorder {
+        fn before(&mut self, span: &Span, storage: &mut Storage) -> Result<()> {
+            let frames = storage.frames.iter().map(|frame| frame.name.clone());
+            let frames = frames.collect();
+            let x = eval_in(storage, "x")?;
+            self.seen.borrow_mut().push((span.line, frames, x));
+            match self.stop_at {
+                Some(line) if line == span.line => Err(Error::Interrupted),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    fn run(src: &str, stop_at: Option<usize>) -> (Result<Value>, Vec<Stop>) {
+        let seen = Rc::default();
+        let mut runtime = Runtime::new();
+        runtime.set_hook(Some(Box::new(Recorder {
+            seen: Rc::clone(&seen),
+            stop_at,
+        })));
+        let res = runtime.eval_str(src);
+        let seen = seen.take();
+        (res, seen)
+    }
+
+    #[test]
+    fn called_before_every_statement() {
+        let (res, seen) = run("fn f(x) {\n  x * 2\n}\nx := 1\nf(5)", None);
+        assert_eq!(res, Ok(Value::Number(10.0)));
+        let lines = seen.iter().map(|(line, ..)| *line).collect::<Vec<_>>();
+        assert_eq!(lines, [4, 5, 2]);
+        // inside `f` its parameter is the `x` in scope
+        assert_eq!(seen[2], (2, vec!["f".to_string()], Value::Number(5.0)));
+    }
+
+    #[test]
+    fn errors_stop_the_run() {
+        let (res, seen) = run("x := 1\nx := 2\nx := 3", Some(2));
+        assert_eq!(res, Err(Error::Interrupted));
+        assert_eq!(seen.last().unwrap().2, Value::Number(1.0));
+    }
+}
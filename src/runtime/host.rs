@@ -0,0 +1,213 @@
+use super::{Error, Result};
+use crate::parser::*;
+use std::{
+    any::Any,
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+    rc::Rc,
+};
+
+/// A Rust object scripts can hold, read properties of and call methods on.
+pub trait HostObject {
+    /// Shown when the object is printed.
+    fn label(&self) -> String;
+
+    /// Looks up `obj.key`, a property value or a method bound to this object.
+    fn get(self: Rc<Self>, key: &str) -> Option<Value>;
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+type Label<T> = Rc<dyn Fn(&T) -> String>;
+type Property<T> = Rc<dyn Fn(&T) -> Value>;
+type Method<T> = Rc<dyn Fn(&mut T, Vec<Value>) -> Result<Value>>;
+
+/// Describes how values of `T` look to scripts, register it with `Runtime::register_type`
+/// and turn values into `Value`s with `Runtime::wrap`.
+pub struct HostType<T> {
+    name: String,
+    label: Option<Label<T>>,
+    properties: HashMap<String, Property<T>>,
+    methods: HashMap<String, Method<T>>,
+}
+
+impl<T: 'static> HostType<T> {
+    pub fn new<S>(name: S) -> Self
+    where
+        S: AsRef<str>,
+    {
+        Self {
+            name: name.as_ref().to_string(),
+            label: None,
+            properties: HashMap::new(),
+            methods: HashMap::new(),
+        }
+    }
+
+    /// How instances are printed, `<name>` by default.
+    pub fn label<F>(mut self, label: F) -> Self
+    where
+        F: Fn(&T) -> String + 'static,
+    {
+        self.label = Some(Rc::new(label));
+        self
+    }
+
+    /// A read-only `obj.name`.
+    pub fn property<S, F>(mut self, name: S, getter: F) -> Self
+    where
+        S: AsRef<str>,
+        F: Fn(&T) -> Value + 'static,
+    {
+        self.properties
+            .insert(name.as_ref().to_string(), Rc::new(getter));
+        self
+    }
+
+    /// An `obj.name(...)` method.
+    pub fn method<S, F>(mut self, name: S, method: F) -> Self
+    where
+        S: AsRef<str>,
+        F: Fn(&mut T, Vec<Value>) -> Result<Value> + 'static,
+    {
+        self.methods.insert(name.as_ref().to_string(), Rc::new(method));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(super) fn instance(self: &Rc<Self>, value: T) -> Value {
+        Value::UserData(UserData {
+            object: Rc::new(Instance {
+                value: RefCell::new(value),
+                ty: self.clone(),
+            }),
+        })
+    }
+}
+
+struct Instance<T> {
+    value: RefCell<T>,
+    ty: Rc<HostType<T>>,
+}
+
+impl<T: 'static> HostObject for Instance<T> {
+    fn label(&self) -> String {
+        match (&self.ty.label, self.value.try_borrow()) {
+            (Some(label), Ok(value)) => label(&value),
+            _ => format!("<{}>", self.ty.name),
+        }
+    }
+
+    fn get(self: Rc<Self>, key: &str) -> Option<Value> {
+        if let Some(property) = self.ty.properties.get(key) {
+            // nil while one of its methods runs and has the value borrowed
+            return self.value.try_borrow().ok().map(|value| property(&value));
+        }
+        let method = self.ty.methods.get(key)?.clone();
+        let key = key.to_string();
+        Some(Value::Closure(Closure::Native(NativeClosure {
+            function: Rc::new(move |args| {
+                // a method that calls back into the script can reach the same object again
+                let mut value = self.value.try_borrow_mut().map_err(|_| {
+                    Error::Native(format!(
+                        "can't call {}.{} while another of its methods runs",
+                        self.ty.name, key
+                    ))
+                })?;
+                method(&mut value, args)
+            }),
+        })))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl UserData {
+    fn instance<T: 'static>(&self) -> Option<&Instance<T>> {
+        self.object.as_any().downcast_ref::<Instance<T>>()
+    }
+
+    /// The wrapped `T`, if this object was made from one and none of its methods is running.
+    pub fn borrow<T: 'static>(&self) -> Option<Ref<'_, T>> {
+        self.instance()?.value.try_borrow().ok()
+    }
+
+    /// Like `borrow`, for changing it.
+    pub fn borrow_mut<T: 'static>(&self) -> Option<RefMut<'_, T>> {
+        self.instance()?.value.try_borrow_mut().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Runtime;
+
+    struct Counter {
+        count: f64,
+    }
+
+    fn runtime() -> Runtime {
+        let mut runtime = Runtime::new();
+        runtime.register_type(
+            HostType::new("counter")
+                .property("count", |counter: &Counter| Value::Number(counter.count))
+                .method("add", |counter, args| {
+                    counter.count += match args.first() {
+                        Some(Value::Number(n)) => *n,
+                        _ => 1.0,
+                    };
+                    Ok(Value::Nil)
+                })
+                .method("call", |_, args| match args.into_iter().next() {
+                    Some(Value::Closure(Closure::Native(f))) => (f.function)(vec![]),
+                    _ => Ok(Value::Nil),
+                }),
+        );
+        let counter = runtime.wrap(Counter { count: 0.0 }).unwrap();
+        runtime.set_global("c", counter);
+        runtime
+    }
+
+    #[test]
+    fn methods_and_properties() {
+        let mut runtime = runtime();
+        let count = runtime.eval_str("c.add()\nc.add(2)\nc.count").unwrap();
+        assert_eq!(count, Value::Number(3.0));
+        let label = runtime.eval_str("c").unwrap();
+        assert!(matches!(label, Value::UserData(data) if data.object.label() == "<counter>"));
+    }
+
+    #[test]
+    fn reentrant_call_fails() {
+        let mut runtime = runtime();
+        let res = runtime.eval_str("c.call(c.add)");
+        assert_eq!(
+            res,
+            Err(Error::Native(
+                "can't call counter.add while another of its methods runs".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn borrows_fail_while_the_value_is_in_use() {
+        let mut runtime = runtime();
+        let Some(Value::UserData(data)) = runtime.get_global("c") else {
+            panic!("c is a host object");
+        };
+        assert!(data.borrow::<String>().is_none());
+        let mut counter = data.borrow_mut::<Counter>().unwrap();
+        counter.count = 5.0;
+        assert!(data.borrow::<Counter>().is_none());
+        assert!(data.borrow_mut::<Counter>().is_none());
+        assert_eq!(runtime.eval_str("c.count"), Ok(Value::Nil));
+        drop(counter);
+        assert_eq!(data.borrow::<Counter>().unwrap().count, 5.0);
+    }
+}
@@ -0,0 +1,226 @@
+use super::{check_interrupt, Error, Result};
+use crate::parser::*;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Caps on a single `Runtime::run`, `None` means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Statements, loop iterations and calls evaluated.
+    pub steps: Option<u64>,
+    /// Nested closure calls. Running low on the host's stack is reported as this limit too,
+    /// whatever the depth, as nested expressions use it as well.
+    pub depth: Option<usize>,
+    /// Approximate bytes in any one value stored in a variable or produced by an operator,
+    /// not a cap on what all of them add up to.
+    pub value_size: Option<usize>,
+    /// Wall-clock time.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            steps: None,
+            // deep enough for sane recursion, `check_stack` stops any that would overflow first
+            depth: Some(256),
+            value_size: None,
+            timeout: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Depth,
+    ValueSize,
+    Timeout,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Steps => "step",
+            Limit::Depth => "call depth",
+            Limit::ValueSize => "value size",
+            Limit::Timeout => "time",
+        })
+    }
+}
+
+/// Stack kept free when evaluating, more than the frames between two `check_stack` in debug
+/// builds.
+const RED_ZONE: usize = 256 * 1024;
+
+/// What a run has used so far of its `Limits`.
+#[derive(Debug, Default)]
+pub struct Budget {
+    pub limits: Limits,
+    steps: u64,
+    /// Steps of every run, never reset.
+    total: u64,
+    depth: usize,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// Resets the counters at the start of a run.
+    pub fn start(&mut self) {
+        self.steps = 0;
+        self.depth = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Counts a step, failing when interrupted or out of steps or time.
+    pub fn tick(&mut self) -> Result<()> {
+        check_interrupt()?;
+        self.steps += 1;
+        self.total += 1;
+        if self.limits.steps.is_some_and(|max| self.steps > max) {
+            return Err(Error::LimitExceeded(Limit::Steps));
+        }
+        if self.deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            return Err(Error::LimitExceeded(Limit::Timeout));
+        }
+        Ok(())
+    }
+
+    /// Steps taken since the runtime was created.
+    pub fn total_steps(&self) -> u64 {
+        self.total
+    }
+
+    /// Counts a call, pair with `leave`.
+    pub fn enter(&mut self) -> Result<()> {
+        self.tick()?;
+        self.check_stack()?;
+        self.depth += 1;
+        if self.limits.depth.is_some_and(|max| self.depth > max) {
+            return Err(Error::LimitExceeded(Limit::Depth));
+        }
+        Ok(())
+    }
+
+    /// Calls in progress.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Fails before the host's stack overflows, which would abort the process.
+    pub fn check_stack(&self) -> Result<()> {
+        match stacker::remaining_stack() {
+            Some(remaining) if remaining < RED_ZONE => Err(Error::LimitExceeded(Limit::Depth)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails when `value` alone is larger than allowed.
+    pub fn check_size(&self, value: &Value) -> Result<()> {
+        match self.limits.value_size {
+            Some(max) if size_of_value(value) > max => Err(Error::LimitExceeded(Limit::ValueSize)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rough heap and inline size of `value` in bytes, closures count as their own size only.
+pub fn size_of_value(value: &Value) -> usize {
+    std::mem::size_of::<Value>()
+        + match value {
+            Value::String(s) => s.len(),
+            Value::Array(array) => array.elements.iter().map(size_of_value).sum(),
+            Value::Set(set) => set
+                .elements
+                .iter()
+                .map(|key| size_of_value(&key.to_value()))
+                .sum(),
+            Value::Table(table) => table
+                .map
+                .iter()
+                .map(|(k, v)| std::mem::size_of::<String>() + k.len() + size_of_value(v))
+                .sum(),
+            _ => 0,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Runtime;
+
+    /// The limit `src` runs into, panics on any other error.
+    fn exceeded(limits: Limits, src: &str) -> Option<Limit> {
+        let mut runtime = Runtime::new();
+        runtime.set_limits(limits);
+        match runtime.eval_str(src) {
+            Ok(_) => None,
+            Err(Error::LimitExceeded(limit)) => Some(limit),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn steps() {
+        let limits = Limits {
+            steps: Some(100),
+            ..Limits::default()
+        };
+        let src = "i := 0\nwhile true { i += 1 }";
+        assert_eq!(exceeded(limits, src), Some(Limit::Steps));
+    }
+
+    #[test]
+    fn depth() {
+        let limits = Limits {
+            depth: Some(10),
+            ..Limits::default()
+        };
+        let src = "fn f(n) { f(n + 1) }\nf(0)";
+        assert_eq!(exceeded(limits, src), Some(Limit::Depth));
+    }
+
+    #[test]
+    fn stack() {
+        // test threads have 2MB of stack, less than these need in debug builds
+        let src = "fn f(n) { if n > 0 { [f(n - 1)][0] + 1 } else { 0 } }\nf(300)";
+        let err = Runtime::new().eval_str(src).unwrap_err();
+        assert_eq!(err.to_string(), "call depth limit exceeded");
+        let unlimited = Limits {
+            depth: None,
+            ..Limits::default()
+        };
+        assert_eq!(
+            exceeded(unlimited, "fn g(n) { g(n + 1) }\ng(0)"),
+            Some(Limit::Depth)
+        );
+    }
+
+    #[test]
+    fn value_size() {
+        let limits = Limits {
+            value_size: Some(300),
+            ..Limits::default()
+        };
+        let src = "s := \"x\"\nwhile true { s = s + s }";
+        assert_eq!(exceeded(limits, src), Some(Limit::ValueSize));
+        // each value is checked on its own, these add up to more
+        let src = "a := \"0123456789\"\nb := a + a\nc := b + b\nd := c + c\ne := d + d";
+        assert_eq!(exceeded(limits, src), None);
+    }
+
+    #[test]
+    fn timeout() {
+        let limits = Limits {
+            timeout: Some(Duration::from_millis(10)),
+            ..Limits::default()
+        };
+        assert_eq!(exceeded(limits, "while true { }"), Some(Limit::Timeout));
+    }
+}
@@ -1,276 +1,735 @@
 #![allow(unused)]
 
+mod builtins;
+mod convert;
+mod de;
+mod debug;
+mod error;
 mod formater;
+mod history;
+mod hook;
+mod host;
+mod limits;
+mod modules;
 mod ops;
+mod ser;
 mod storage;
 
-use std::collections::HashMap;
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use crate::parser::*;
+pub use builtins::diff;
+pub use de::from_value;
+pub use debug::*;
+pub use error::*;
 pub use formater::*;
+pub use history::*;
+pub use hook::*;
+pub use host::*;
+pub use limits::*;
+pub use modules::*;
+pub use ser::to_value;
 pub use storage::*;
 
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Asks the running evaluation to stop at the next statement, loop iteration or call. There is one
+/// flag for the process, the first runtime to check it stops.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+fn check_interrupt() -> Result<()> {
+    if INTERRUPTED.swap(false, Ordering::SeqCst) {
+        Err(Error::Interrupted)
+    } else {
+        Ok(())
+    }
+}
+
 pub struct Runtime {
     pub storage: Storage,
+    types: HashMap<TypeId, Rc<dyn Any>>,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Runtime {
     pub fn new() -> Self {
         Self {
             storage: Storage::new(),
+            types: HashMap::new(),
         }
     }
 
-    pub fn register_fn<S>(&mut self, name: S, func: fn(Vec: Vec<Value>) -> Value)
+    /// A runtime with the functions of the language: `assert`, `assert_eq`, `set`, `history` and
+    /// `print_options`. Input and output are left to the host.
+    pub fn with_std() -> Self {
+        let mut runtime = Self::new();
+        builtins::register(&mut runtime);
+        runtime
+    }
+
+    /// Lets values of `T` be passed to scripts with `wrap`.
+    pub fn register_type<T: 'static>(&mut self, host_type: HostType<T>) {
+        self.types
+            .insert(TypeId::of::<T>(), Rc::new(Rc::new(host_type)));
+    }
+
+    /// Turns `value` into a `Value::UserData`, `T` must be registered with `register_type`.
+    pub fn wrap<T: 'static>(&self, value: T) -> Option<Value> {
+        let host_type = self
+            .types
+            .get(&TypeId::of::<T>())?
+            .downcast_ref::<Rc<HostType<T>>>()?;
+        Some(host_type.instance(value))
+    }
+
+    /// Makes `func` callable from scripts as `name(...)`.
+    pub fn register_fn<S, F>(&mut self, name: S, func: F)
+    where
+        S: AsRef<str>,
+        F: Fn(Vec<Value>) -> Value + 'static,
+    {
+        self.register_try_fn(name, move |args| Ok(func(args)))
+    }
+
+    /// Like `register_fn`, for functions that can fail, errors stop the script.
+    pub fn register_try_fn<S, F>(&mut self, name: S, func: F)
     where
         S: AsRef<str>,
+        F: Fn(Vec<Value>) -> Result<Value> + 'static,
     {
         self.storage.global_scope_mut().set(
             name,
-            Value::Closure(Closure::Native(NativeClosure { function: func })),
+            Value::Closure(Closure::Native(NativeClosure {
+                function: Rc::new(func),
+            })),
         )
     }
 
-    pub fn run(&mut self, program: Program) -> Value {
-        Block {
-            statements: program.statements,
-            functions: program.functions,
-        }
-        .eval(&mut self.storage)
+    pub fn get_global<S>(&self, name: S) -> Option<Value>
+    where
+        S: AsRef<str>,
+    {
+        self.storage.global_scope().get_optional(name)
+    }
+
+    pub fn set_global<S, V>(&mut self, name: S, value: V)
+    where
+        S: AsRef<str>,
+        V: Into<Value>,
+    {
+        self.storage.global_scope_mut().set(name, value.into())
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.storage.budget.limits
+    }
+
+    /// Caps how much work each following `run` or `eval_str` may do.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.storage.budget.limits = limits;
+    }
+
+    pub fn strict(&self) -> bool {
+        self.storage.strict
+    }
+
+    /// Makes unknown names, undeclared assignments, arity mismatches and operators applied
+    /// to the wrong types errors. Also enabled by a program starting with `"use strict"`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.storage.strict = strict;
+    }
+
+    /// Starts or stops recording assignments, see `history`.
+    pub fn set_history(&mut self, tracking: Tracking) {
+        self.storage.history.borrow_mut().tracking = tracking;
+    }
+
+    /// Assignments recorded so far.
+    pub fn history(&self) -> Rc<RefCell<History>> {
+        self.storage.history.clone()
+    }
+
+    /// Installs `hook` to be called before every statement, `None` removes it.
+    pub fn set_hook(&mut self, hook: Option<Box<dyn Hook>>) {
+        self.storage.hook = hook;
+    }
+
+    /// Parses and runs `src`, returning the value of its last statement.
+    pub fn eval_str(&mut self, src: &str) -> Result<Value> {
+        let program = parse(src)?;
+        self.run(program)
     }
+
+    pub fn run(&mut self, program: Program) -> Result<Value> {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        self.storage.budget.start();
+        let depth = self.storage.scopes.len();
+        self.storage.strict |= is_strict(&program.statements);
+        // top level code runs in the global scope so definitions outlive a single run
+        let res = eval_body(
+            &program.functions,
+            &program.statements,
+            &program.spans,
+            &mut self.storage,
+        );
+        // an error can leave scopes pushed by unfinished blocks and calls behind
+        self.storage.scopes.truncate(depth);
+        self.storage.frames.clear();
+        res
+    }
+}
+
+/// Whether `statements` begin with the `"use strict"` pragma.
+fn is_strict(statements: &[Statement]) -> bool {
+    matches!(
+        statements.first(),
+        Some(Statement::Expression(Expression::Value(Value::String(pragma)))) if pragma == "use strict"
+    )
 }
 
 trait Evaluate {
-    fn eval(&self, storage: &mut Storage) -> Value;
+    fn eval(&self, storage: &mut Storage) -> Result<Value>;
 }
 
 impl Evaluate for Statement {
-    fn eval(&self, storage: &mut Storage) -> Value {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
         match self {
             Statement::Expression(expression) => expression.eval(storage),
-            Statement::Set(set) => set.eval(storage),
             Statement::Define(define) => define.eval(storage),
-            Statement::DefineAndSet(define_and_set) => define_and_set.eval(storage),
             Statement::While(while_statement) => while_statement.eval(storage),
             Statement::For(for_statement) => for_statement.eval(storage),
+            Statement::Import(import) => import.eval(storage),
+            Statement::Reference(reference) => {
+                if storage.strict && !storage.has(&reference.target) {
+                    return Err(Error::Undefined(reference.target.clone()));
+                }
+                let cell = storage.reference(&reference.target);
+                storage.bind(&reference.identifier, cell.clone());
+                let value = cell.borrow().clone();
+                Ok(value)
+            }
+            Statement::Destructure(destructure_statement) => {
+                let value = destructure_statement.expression.eval(storage)?;
+                storage.at(&destructure_statement.span);
+                destructure(&destructure_statement.pattern, value.clone(), storage)?;
+                Ok(value)
+            }
         }
     }
 }
 
+/// Evaluates call arguments, with the cell of those that name a reference.
+fn eval_arguments(
+    arguments: &[Expression],
+    storage: &mut Storage,
+) -> Result<(Vec<Value>, Vec<Option<Cell>>)> {
+    let cells = arguments
+        .iter()
+        .map(|arg| match arg {
+            Expression::Identifier(name) => storage.cell(name),
+            _ => None,
+        })
+        .collect();
+    let args = arguments
+        .iter()
+        .map(|arg| arg.eval(storage))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((args, cells))
+}
+
 impl Evaluate for ForStatement {
-    fn eval(&self, storage: &mut Storage) -> Value {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
         storage.push_scope();
-        self.initializer.eval(storage);
+        self.initializer.eval(storage)?;
         loop {
-            let cond = self.condition.eval(storage);
+            storage.budget.tick()?;
+            let cond = self.condition.eval(storage)?;
             if !ops::as_bool(cond) {
                 break;
             }
             // maybe clear _ ?
-            self.statement.eval(storage);
-            self.increment.eval(storage);
+            self.statement.eval(storage)?;
+            self.increment.eval(storage)?;
         }
         storage.pop_scope();
-        Value::Nil // TODO: return break value
+        Ok(Value::Nil) // TODO: return break value
     }
 }
 
 impl Evaluate for WhileStatement {
-    fn eval(&self, storage: &mut Storage) -> Value {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
         storage.push_scope();
         let mut value = Value::Nil;
         loop {
-            let cond = self.expression.eval(storage);
+            storage.budget.tick()?;
+            let cond = self.expression.eval(storage)?;
             if !ops::as_bool(cond) {
                 break;
             }
-            value = self.statement.eval(storage);
+            value = self.statement.eval(storage)?;
         }
         storage.pop_scope();
-        value
+        Ok(value)
     }
 }
 
 impl Evaluate for IfExpression {
-    fn eval(&self, storage: &mut Storage) -> Value {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
         storage.push_scope();
         let mut value = None;
         for (condition, statement) in &self.conditionals {
-            let cond = condition.eval(storage);
+            let cond = condition.eval(storage)?;
             if ops::as_bool(cond) {
-                value = Some(statement.eval(storage));
+                value = Some(statement.eval(storage)?);
                 break;
             }
         }
 
         storage.pop_scope();
-        value.unwrap_or_else(|| {
-            if let Some(statement) = &self.otherwise {
-                statement.eval(storage)
-            } else {
-                Value::Nil
-            }
-        })
+        match value {
+            Some(value) => Ok(value),
+            None => match &self.otherwise {
+                Some(statement) => statement.eval(storage),
+                None => Ok(Value::Nil),
+            },
+        }
     }
 }
 
-impl Evaluate for SetStatement {
-    fn eval(&self, storage: &mut Storage) -> Value {
-        let new = self.expression.eval(storage);
+impl Evaluate for AssignExpression {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        let name = &self.target.identifier;
+        if self.op != SetOp::Define && storage.strict && !storage.has(name) {
+            return Err(Error::Undeclared(name.clone()));
+        }
+        let new = self.expression.eval(storage)?;
+        let path = self
+            .target
+            .path
+            .iter()
+            .map(|accessor| match accessor {
+                Accessor::Index(index) => index.eval(storage),
+                Accessor::Key(key) => Ok(Value::String(key.clone())),
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         let value = match self.op {
-            SetOp::Set => new,
-            _ => {
-                let curr = storage.get(&self.identifier).clone();
-                match self.op {
-                    SetOp::Set => unreachable!(),
+            SetOp::Define | SetOp::Set => new,
+            SetOp::Increment | SetOp::Decrement => {
+                let curr = path.iter().fold(storage.get(name), |value, key| {
+                    ops::index(value, key.clone())
+                });
+                let (op, operands) = (self.op.symbol(), operands(&[&curr, &new]));
+                let value = match self.op {
                     SetOp::Increment => ops::add(curr, new),
-                    SetOp::Decrement => ops::sub(curr, new),
-                }
+                    _ => ops::sub(curr, new),
+                };
+                check_operands(value, op, operands, storage)?
             }
         };
+        storage.budget.check_size(&value)?;
+        storage.at(&self.span);
 
-        storage.set(&self.identifier, value.clone());
-        value
+        if self.op == SetOp::Define {
+            storage.define(name, value.clone());
+        } else if path.is_empty() {
+            storage.set(name, value.clone());
+        } else {
+            let mut root = storage.get(name);
+            if !ops::set_index(&mut root, &path, value.clone()) {
+                let operands = operands(&[&root, &path[0]]);
+                return check_operands(Value::Nil, self.op.symbol(), operands, storage);
+            }
+            storage.budget.check_size(&root)?;
+            storage.set(name, root);
+        }
+        Ok(value)
     }
 }
 
 impl Evaluate for DefineStatement {
-    fn eval(&self, storage: &mut Storage) -> Value {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        storage.at(&self.span);
         storage.define(&self.identifier, Value::Nil);
-        Value::Nil
-    }
-}
-
-impl Evaluate for DefineAndSetStatement {
-    fn eval(&self, storage: &mut Storage) -> Value {
-        let value = self.expression.eval(storage);
-        storage.define(&self.identifier, value.clone());
-        value
+        Ok(Value::Nil)
     }
 }
 
 impl Evaluate for Value {
-    fn eval(&self, _storage: &mut Storage) -> Value {
-        self.clone()
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        Ok(match self {
+            Value::Closure(Closure::Normal(closure)) => modules::capture(closure, storage),
+            _ => self.clone(),
+        })
     }
 }
 
 impl Evaluate for Expression {
-    fn eval(&self, storage: &mut Storage) -> Value {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        storage.budget.check_stack()?;
         match self {
             Expression::Value(value) => value.eval(storage),
             Expression::Infixed(operation) => operation.eval(storage),
             Expression::Prefixed(prefixed) => prefixed.eval(storage),
             Expression::Postfixed(postfixed) => postfixed.eval(storage),
-            Expression::Identifier(identifier) => storage.get(identifier),
+            Expression::Assign(assignment) => assignment.eval(storage),
+            Expression::Identifier(identifier) => match storage.get_optional(identifier) {
+                Some(value) => Ok(value),
+                None if storage.strict => Err(Error::Undefined(identifier.clone())),
+                None => Ok(Value::Nil),
+            },
             Expression::Block(block) => block.eval(storage),
             Expression::Map(map) => map.eval(storage),
             Expression::FunctionCall(call) => call.eval(storage),
             Expression::Array(array) => array.eval(storage),
             Expression::If(if_expression) => if_expression.eval(storage),
             Expression::Table(table) => table.eval(storage),
+            Expression::Set(set) => {
+                let mut elements = HashSet::new();
+                for element in &set.elements {
+                    let value = element.eval(storage)?;
+                    elements.insert(Key::new(&value).ok_or(Error::Type {
+                        expected: "number, string, boolean, nil or array",
+                        found: type_name(&value),
+                    })?);
+                }
+                Ok(Value::Set(ValuesSet { elements }))
+            }
         }
     }
 }
 
 impl Evaluate for ExpressionsTable {
-    fn eval(&self, storage: &mut Storage) -> Value {
-        Value::Table(ValuesTable {
-            map: self
-                .map
-                .iter()
-                .map(|(key, value)| (key.clone(), value.eval(storage)))
-                .collect::<HashMap<_, _>>(),
-        })
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        let mut map = HashMap::new();
+        for (key, value) in &self.entries {
+            let key = match key {
+                TableKey::Fixed(key) => key.clone(),
+                TableKey::Computed(key) => match key.eval(storage)? {
+                    Value::String(key) => key,
+                    // like operators, a key of the wrong type gives nothing outside strict mode
+                    key if storage.strict => {
+                        return Err(Error::Type {
+                            expected: "string",
+                            found: type_name(&key),
+                        })
+                    }
+                    _ => continue,
+                },
+            };
+            map.insert(key, value.eval(storage)?);
+        }
+        Ok(Value::Table(ValuesTable { map }))
     }
 }
 
 impl Evaluate for ExpressionsArray {
-    fn eval(&self, storage: &mut Storage) -> Value {
-        Value::Array(ValuesArray {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        Ok(Value::Array(ValuesArray {
             elements: self
                 .elements
                 .iter()
                 .map(|element| element.eval(storage))
-                .collect::<Vec<_>>(),
-        })
+                .collect::<Result<Vec<_>>>()?,
+        }))
     }
 }
 
 impl Evaluate for FunctionCall {
-    fn eval(&self, storage: &mut Storage) -> Value {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
         if let Some(func) = storage.get_optional(&self.name) {
             match func {
                 Value::Closure(func) => {
-                    let args = self
-                        .arguments
-                        .iter()
-                        .map(|arg| arg.eval(storage))
-                        .collect::<Vec<_>>();
-                    ops::run_closure(func, args, storage)
+                    let (args, cells) = eval_arguments(&self.arguments, storage)?;
+                    call(&self.name, func, args, cells, storage)
                 }
-                _ => Value::Nil,
+                value => not_callable(&value, storage),
             }
+        } else if storage.strict {
+            Err(Error::Undefined(self.name.clone()))
         } else {
-            Value::Nil
+            Ok(Value::Nil)
         }
     }
 }
 
+/// Runs a call, keeping `Storage::frames` up to date while a hook is installed.
+fn call(
+    name: &str,
+    func: Closure,
+    args: Vec<Value>,
+    cells: Vec<Option<Cell>>,
+    storage: &mut Storage,
+) -> Result<Value> {
+    let traced = storage.hook.is_some();
+    if traced {
+        let scope = storage.scopes.len();
+        storage.frames.push(Frame {
+            name: name.to_string(),
+            scope,
+        });
+    }
+    let res = ops::run_closure_with_refs(func, args, cells, storage);
+    if traced {
+        storage.frames.pop();
+    }
+    res
+}
+
+fn not_callable(value: &Value, storage: &Storage) -> Result<Value> {
+    if storage.strict {
+        Err(Error::Type {
+            expected: "closure",
+            found: type_name(value),
+        })
+    } else {
+        Ok(Value::Nil)
+    }
+}
+
 impl Evaluate for MapExpression {
-    fn eval(&self, storage: &mut Storage) -> Value {
-        let input = self.input.eval(storage);
-        let mut fallback = Value::Nil;
-
-        for (cases, value) in &self.map {
-            for case in cases {
-                if let Expression::Identifier(ident) = case {
-                    if ident == "_" {
-                        fallback = value.eval(storage);
-                        continue;
-                    }
-                }
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        let input = self.input.eval(storage)?;
 
-                let case = case.eval(storage);
-                let eq = ops::eq(input.clone(), case);
-                if ops::as_bool(eq) {
-                    return value.eval(storage);
+        for arm in &self.arms {
+            for pattern in &arm.patterns {
+                let mut bindings = vec![];
+                if !matches(pattern, &input, &mut bindings, storage)? {
+                    continue;
+                }
+                storage.push_scope();
+                for (name, value) in bindings {
+                    storage.define(name, value);
+                }
+                let guard = match &arm.guard {
+                    Some(guard) => ops::as_bool(guard.eval(storage)?),
+                    None => true,
+                };
+                let value = match guard {
+                    true => Some(arm.value.eval(storage)?),
+                    false => None,
+                };
+                storage.pop_scope();
+                // a failed guard lets the next pattern of the arm try
+                if let Some(value) = value {
+                    return Ok(value);
                 }
             }
         }
 
-        fallback
+        Ok(Value::Nil)
+    }
+}
+
+thread_local! {
+    /// Regexes of patterns, compiled the first time they're tried.
+    static REGEXES: RefCell<HashMap<String, regex::Regex>> = RefCell::new(HashMap::new());
+}
+
+/// Defines the names `pattern` binds in the current scope. A value that doesn't fit gives them
+/// all nil, or is an error in strict mode.
+fn destructure(pattern: &Pattern, value: Value, storage: &mut Storage) -> Result<()> {
+    let mut bindings = vec![];
+    if !matches(pattern, &value, &mut bindings, storage)? {
+        if storage.strict {
+            return Err(Error::Destructure {
+                pattern: pattern.unparse(),
+                found: type_name(&value),
+            });
+        }
+        bindings.clear();
+        bound_names(pattern, &mut |name| bindings.push((name, Value::Nil)));
+    }
+    for (name, value) in bindings {
+        storage.define(name, value);
+    }
+    Ok(())
+}
+
+/// Every name `pattern` would bind when matching.
+fn bound_names(pattern: &Pattern, f: &mut impl FnMut(String)) {
+    match pattern {
+        Pattern::Wildcard | Pattern::Value(_) => (),
+        Pattern::Binding(name) | Pattern::Rest(name) => f(name.clone()),
+        Pattern::Type(_, name) if name != "_" => f(name.clone()),
+        Pattern::Type(..) => (),
+        Pattern::Array(patterns) => patterns.iter().for_each(|p| bound_names(p, f)),
+        Pattern::Table(fields) => fields.iter().for_each(|(_, p)| bound_names(p, f)),
+        Pattern::Regex(source) => regex::Regex::new(source)
+            .unwrap()
+            .capture_names()
+            .flatten()
+            .for_each(|name| f(name.to_string())),
     }
 }
 
+/// Whether `value` fits `pattern`, pushing the names it binds to `bindings`.
+fn matches(
+    pattern: &Pattern,
+    value: &Value,
+    bindings: &mut Vec<(String, Value)>,
+    storage: &mut Storage,
+) -> Result<bool> {
+    Ok(match (pattern, value) {
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Binding(name), _) | (Pattern::Rest(name), _) => {
+            bindings.push((name.clone(), value.clone()));
+            true
+        }
+        (Pattern::Value(expression), _) => match (expression.eval(storage)?, value) {
+            (range @ (Value::ExclusiveRange(..) | Value::InclusiveRange(..)), Value::Number(_)) => {
+                ops::as_bool(ops::is_in(value.clone(), range))
+            }
+            (expected, _) => ops::as_bool(ops::eq(value.clone(), expected)),
+        },
+        (Pattern::Type(kind, name), _) => {
+            let found = match type_name(value) {
+                "native" => "closure",
+                found => found,
+            };
+            if kind != "any" && kind != found {
+                return Ok(false);
+            }
+            if name != "_" {
+                bindings.push((name.clone(), value.clone()));
+            }
+            true
+        }
+        (Pattern::Array(patterns), Value::Array(array)) => {
+            let elements = &array.elements;
+            let rest = patterns
+                .iter()
+                .position(|pattern| matches!(pattern, Pattern::Rest(_)));
+            let fits = match rest {
+                Some(_) => elements.len() + 1 >= patterns.len(),
+                None => elements.len() == patterns.len(),
+            };
+            if !fits {
+                return Ok(false);
+            }
+            // the patterns after the rest take the last elements
+            let taken = elements.len() + 1 - patterns.len();
+            for (i, pattern) in patterns.iter().enumerate() {
+                let value = match rest {
+                    Some(rest) if i == rest => Value::Array(ValuesArray {
+                        elements: elements[i..i + taken].to_vec(),
+                    }),
+                    Some(rest) if i > rest => elements[i + taken - 1].clone(),
+                    _ => elements[i].clone(),
+                };
+                if !matches(pattern, &value, bindings, storage)? {
+                    return Ok(false);
+                }
+            }
+            true
+        }
+        (Pattern::Table(fields), Value::Table(table)) => {
+            for (key, pattern) in fields {
+                match table.map.get(key) {
+                    Some(value) if matches(pattern, value, bindings, storage)? => (),
+                    _ => return Ok(false),
+                }
+            }
+            true
+        }
+        (Pattern::Regex(source), Value::String(string)) => REGEXES.with_borrow_mut(|regexes| {
+            let regex = regexes
+                .entry(source.clone())
+                .or_insert_with(|| regex::Regex::new(source).unwrap());
+            let Some(captures) = regex.captures(string) else {
+                return false;
+            };
+            for name in regex.capture_names().flatten() {
+                let value = captures.name(name).map_or(Value::Nil, |group| {
+                    Value::String(group.as_str().to_string())
+                });
+                bindings.push((name.to_string(), value));
+            }
+            true
+        }),
+        _ => false,
+    })
+}
+
 impl Evaluate for Block {
-    fn eval(&self, storage: &mut Storage) -> Value {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
         storage.push_scope();
-        for function in &self.functions {
-            storage.set(
-                &function.name,
-                Value::Closure(Closure::Normal(function.closure.clone())),
-            );
+        let res = eval_body(&self.functions, &self.statements, &self.spans, storage)?;
+        storage.pop_scope();
+        Ok(res)
+    }
+}
+
+fn eval_body(
+    functions: &[Function],
+    statements: &[Statement],
+    spans: &[Span],
+    storage: &mut Storage,
+) -> Result<Value> {
+    // `fn` definitions aren't statements, they're recorded without a span
+    storage.history.borrow_mut().span = None;
+    for function in functions {
+        let closure = modules::capture(&function.closure, storage);
+        storage.set(&function.name, closure);
+    }
+    for (i, statement) in statements.iter().enumerate() {
+        storage.budget.tick()?;
+        // arguments are recorded at the statement making the call, assignments at their own
+        if let Some(span) = spans.get(i) {
+            storage.at(span);
         }
-        for statement in &self.statements {
-            let value = statement.eval(storage);
-            storage.set("_", value);
+        // taken out while it runs, so statements it evaluates don't call it again
+        if let (Some(mut hook), Some(span)) = (storage.hook.take(), spans.get(i)) {
+            let res = hook.before(span, storage);
+            storage.hook = Some(hook);
+            res?;
         }
-        let res = storage.get("_");
-        storage.pop_scope();
-        res
+        let value = statement.eval(storage)?;
+        storage.set("_", value);
     }
+    Ok(storage.get("_"))
 }
 
 impl Evaluate for Operation {
-    fn eval(&self, storage: &mut Storage) -> Value {
-        let lhs = self.lhs.eval(storage);
-        let rhs = self.rhs.eval(storage);
-        match self.infix {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        let lhs = self.lhs.eval(storage)?;
+        // the right side is left alone when the left one decides, `&&` of anything but true
+        // and `||` of anything but false can't turn out otherwise
+        let decided = match (&self.infix, &lhs) {
+            (Operator::And, _) => {
+                (!ops::as_bool(lhs.clone())).then(|| ops::and(lhs.clone(), Value::Boolean(false)))
+            }
+            (Operator::Or, Value::Boolean(false)) => None,
+            (Operator::Or, _) => Some(ops::or(lhs.clone(), Value::Boolean(true))),
+            _ => None,
+        };
+        if let Some(value) = decided {
+            return check_operands(value, self.infix.symbol(), operands(&[&lhs]), storage);
+        }
+        match (&self.infix, &lhs) {
+            (Operator::Coalesce, Value::Nil) => return self.rhs.eval(storage),
+            (Operator::Coalesce, _) => return Ok(lhs),
+            _ => (),
+        }
+        let rhs = self.rhs.eval(storage)?;
+        let operands = operands(&[&lhs, &rhs]);
+        let value = match self.infix {
             Operator::Add => ops::add(lhs, rhs),
             Operator::Sub => ops::sub(lhs, rhs),
-            Operator::Mul => ops::mul(lhs, rhs, storage),
+            Operator::Mul => ops::mul(lhs, rhs, storage)?,
             Operator::Div => ops::div(lhs, rhs),
             Operator::Eq => ops::eq(lhs, rhs),
             Operator::Neq => ops::neq(lhs, rhs),
@@ -280,54 +739,185 @@ impl Evaluate for Operation {
             Operator::Lte => ops::lte(lhs, rhs),
             Operator::And => ops::and(lhs, rhs),
             Operator::Or => ops::or(lhs, rhs),
+            Operator::Coalesce => unreachable!(),
             Operator::Dollar => ops::dollar(lhs, rhs),
             Operator::DoubleDollar => ops::double_dollar(lhs, rhs),
             Operator::ExclusiveRange => ops::exclusive_range(lhs, rhs),
             Operator::InclusiveRange => ops::inclusive_range(lhs, rhs),
-            Operator::Modulo => ops::modulo(lhs, rhs, storage),
-            Operator::At => ops::at(lhs, rhs, storage),
-        }
+            Operator::Modulo => ops::modulo(lhs, rhs, storage)?,
+            Operator::At => ops::at(lhs, rhs, storage)?,
+            Operator::Ampersand => ops::ampersand(lhs, rhs),
+            Operator::In => ops::is_in(lhs, rhs),
+        };
+        let value = check_operands(value, self.infix.symbol(), operands, storage)?;
+        storage.budget.check_size(&value)?;
+        Ok(value)
     }
 }
 
 impl Evaluate for PrefixedExpression {
-    fn eval(&self, storage: &mut Storage) -> Value {
-        match self {
-            PrefixedExpression::Negative(expression) => ops::negate(expression.eval(storage)),
-            PrefixedExpression::Not(expression) => ops::not(expression.eval(storage)),
-            PrefixedExpression::Hash(expression) => ops::hash(expression.eval(storage)),
-            PrefixedExpression::DoubleHash(expression) => {
-                ops::double_hash(expression.eval(storage))
-            }
-            PrefixedExpression::TripleHash(expression) => {
-                ops::triple_hash(expression.eval(storage))
-            }
-        }
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        let (PrefixedExpression::Negative(expression)
+        | PrefixedExpression::Not(expression)
+        | PrefixedExpression::Hash(expression)
+        | PrefixedExpression::DoubleHash(expression)
+        | PrefixedExpression::TripleHash(expression)) = self;
+        let value = expression.eval(storage)?;
+        let operands = operands(&[&value]);
+        let value = match self {
+            PrefixedExpression::Negative(_) => ops::negate(value),
+            PrefixedExpression::Not(_) => ops::not(value),
+            PrefixedExpression::Hash(_) => ops::hash(value),
+            PrefixedExpression::DoubleHash(_) => ops::double_hash(value),
+            PrefixedExpression::TripleHash(_) => ops::triple_hash(value),
+        };
+        check_operands(value, self.symbol(), operands, storage)
+    }
+}
+
+/// Type names of an operator's operands, for `Error::Operands`.
+fn operands(values: &[&Value]) -> String {
+    values
+        .iter()
+        .map(|value| type_name(value))
+        .collect::<Vec<_>>()
+        .join(" and ")
+}
+
+/// Operators give nil for operands of the wrong type, strict mode turns that into an error.
+fn check_operands(
+    value: Value,
+    op: &'static str,
+    operands: String,
+    storage: &Storage,
+) -> Result<Value> {
+    match value {
+        Value::Nil if storage.strict => Err(Error::Operands { op, operands }),
+        value => Ok(value),
     }
 }
 
 impl Evaluate for PostfixedExpression {
-    fn eval(&self, storage: &mut Storage) -> Value {
-        match self {
-            PostfixedExpression::Debug(expression) => {
-                let value = expression.eval(storage);
-                println!("{}", value.fmt_debug());
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        Ok(match self {
+            PostfixedExpression::Debug(expression, info) => {
+                let value = expression.eval(storage)?;
+                debug_print(info, &fmt_debug_with(&value, &PrintOptions::INTERACTIVE));
                 value
             }
             PostfixedExpression::Print(expression) => {
-                let value = expression.eval(storage);
+                let value = expression.eval(storage)?;
                 println!("{}", value.fmt_print());
                 value
             }
             PostfixedExpression::Index(expression, index) => {
-                let value = expression.eval(storage);
-                let index = index.eval(storage);
+                let value = expression.eval(storage)?;
+                let index = index.eval(storage)?;
                 ops::index(value, index)
             }
             PostfixedExpression::DotIndex(expression, index) => {
-                let value = expression.eval(storage);
+                let value = expression.eval(storage)?;
                 ops::dot_index(value, index.clone())
             }
-        }
+            PostfixedExpression::OptionalDotIndex(expression, index) => {
+                match expression.eval(storage)? {
+                    Value::Nil => Value::Nil,
+                    value => ops::dot_index(value, index.clone()),
+                }
+            }
+            PostfixedExpression::Call(expression, arguments) => {
+                let func = expression.eval(storage)?;
+                let (args, cells) = eval_arguments(arguments, storage)?;
+                match func {
+                    Value::Closure(func) => {
+                        let name = match expression.as_ref() {
+                            Expression::Identifier(name) => name,
+                            Expression::Postfixed(PostfixedExpression::DotIndex(_, key)) => key,
+                            _ => "<closure>",
+                        };
+                        call(name, func, args, cells, storage)?
+                    }
+                    value => not_callable(&value, storage)?,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globals_outlive_a_run() {
+        let mut runtime = Runtime::new();
+        runtime.set_global("base", 10);
+        runtime.eval_str("total := base + 5").unwrap();
+        assert_eq!(runtime.get_global("total"), Some(Value::Number(15.0)));
+        assert_eq!(runtime.eval_str("total * 2"), Ok(Value::Number(30.0)));
+        assert_eq!(runtime.get_global("missing"), None);
+    }
+
+    #[test]
+    fn native_functions() {
+        let mut runtime = Runtime::new();
+        runtime.register_fn("count", |args| Value::Number(args.len() as f64));
+        runtime.register_try_fn("fail", |_| Err(Error::Native("no".to_string())));
+        assert_eq!(runtime.eval_str("count(1, 2, 3)"), Ok(Value::Number(3.0)));
+        assert_eq!(
+            runtime.eval_str("x := 1\nfail()\nx := 2"),
+            Err(Error::Native("no".to_string()))
+        );
+        // the statements before the error still ran
+        assert_eq!(runtime.get_global("x"), Some(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let mut runtime = Runtime::new();
+        assert!(matches!(runtime.eval_str("x := ("), Err(Error::Parse(_))));
+    }
+
+    fn strict(src: &str) -> Result<Value> {
+        let mut runtime = Runtime::new();
+        runtime.set_strict(true);
+        runtime.eval_str(src)
+    }
+
+    #[test]
+    fn strict_errors() {
+        assert_eq!(strict("y"), Err(Error::Undefined("y".to_string())));
+        assert_eq!(strict("y = 1"), Err(Error::Undeclared("y".to_string())));
+        assert_eq!(
+            strict("fn f(a, b) { a }\nf(1)"),
+            Err(Error::Arity {
+                expected: 2,
+                found: 1,
+            })
+        );
+        assert_eq!(
+            strict("1 + nil"),
+            Err(Error::Operands {
+                op: "+",
+                operands: "number and nil".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn lenient_without_strict() {
+        let mut runtime = Runtime::new();
+        assert_eq!(runtime.eval_str("y"), Ok(Value::Nil));
+        assert_eq!(runtime.eval_str("1 + nil"), Ok(Value::Nil));
+        assert_eq!(runtime.eval_str("fn f(a, b) { b }\nf(1)"), Ok(Value::Nil));
+    }
+
+    #[test]
+    fn strict_pragma() {
+        let mut runtime = Runtime::new();
+        let res = runtime.eval_str("\"use strict\"\ny");
+        assert_eq!(res, Err(Error::Undefined("y".to_string())));
+        // and it stays on for later runs
+        assert!(runtime.strict());
     }
 }
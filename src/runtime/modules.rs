@@ -0,0 +1,265 @@
+use super::{eval_body, is_strict, Error, Evaluate, Result, Storage};
+use crate::parser::*;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// Modules imported by a runtime, each one is evaluated once.
+#[derive(Default)]
+pub struct Modules {
+    /// Exported table of every module evaluated so far, by canonical path.
+    cache: HashMap<PathBuf, Value>,
+    /// Keeps module globals alive, closures only hold weak references to them.
+    globals: Vec<Globals>,
+    /// Modules being evaluated, innermost last.
+    loading: Vec<PathBuf>,
+}
+
+/// Directories searched after the importing file's own, from `QS_PATH`.
+fn search_path() -> Vec<PathBuf> {
+    env::var_os("QS_PATH")
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default()
+}
+
+fn resolve(import: &ImportStatement) -> Result<PathBuf> {
+    // <repl> and <input> have an empty parent, so their imports are relative to the working directory
+    let base = Path::new(&import.source)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let candidates = std::iter::once(base)
+        .chain(search_path())
+        .map(|dir| dir.join(&import.path))
+        .collect::<Vec<_>>();
+    candidates
+        .iter()
+        .find(|path| path.is_file())
+        .and_then(|path| path.canonicalize().ok())
+        .ok_or_else(|| {
+            Error::Import(format!(
+                "module \"{}\" not found, tried {}",
+                import.path,
+                candidates
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })
+}
+
+/// Name the module is bound to, `lib` for `import "lib.qs"`.
+fn binding(import: &ImportStatement) -> Result<String> {
+    if let Some(name) = &import.name {
+        return Ok(name.clone());
+    }
+    Path::new(&import.path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .filter(|stem| {
+            !stem.is_empty() && stem.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+        .map(str::to_string)
+        .ok_or_else(|| {
+            Error::Import(format!(
+                "\"{}\" isn't a valid name, use `import \"{}\" as name`",
+                import.path, import.path
+            ))
+        })
+}
+
+/// Gives the closures in `value`, also those kept in tables and arrays, the module's globals.
+/// Closures that already have some came from another module and keep theirs.
+fn attach(value: &mut Value, env: &Env) {
+    match value {
+        Value::Closure(Closure::Normal(closure)) if closure.env.is_none() => {
+            closure.env = Some(env.clone())
+        }
+        Value::Table(table) => table.map.values_mut().for_each(|value| attach(value, env)),
+        Value::Array(array) => array
+            .elements
+            .iter_mut()
+            .for_each(|value| attach(value, env)),
+        _ => {}
+    }
+}
+
+/// A closure created while `storage` runs a module's function, which keeps seeing its globals.
+pub(super) fn capture(closure: &NormalClosure, storage: &Storage) -> Value {
+    let mut closure = closure.clone();
+    if let (None, Some(globals)) = (&closure.env, storage.env()) {
+        closure.env = Some(Env {
+            globals: Rc::downgrade(globals),
+        });
+    }
+    Value::Closure(Closure::Normal(closure))
+}
+
+/// Evaluates the module at `path` in a storage of its own and returns its exported table.
+fn load(path: &Path, storage: &mut Storage) -> Result<Value> {
+    if let Some(i) = storage.modules.loading.iter().position(|p| p == path) {
+        let cycle = storage.modules.loading[i..]
+            .iter()
+            .chain([&path.to_path_buf()])
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        return Err(Error::Import(format!("import cycle: {}", cycle)));
+    }
+    let src = fs::read_to_string(path)
+        .map_err(|e| Error::Import(format!("can't read {}: {}", path.display(), e)))?;
+    let program = parse_named(&src, &path.display().to_string())?;
+
+    // modules see the host's native functions but none of the importer's variables
+    let prelude = storage
+        .global_scope()
+        .data
+        .iter()
+        .filter(|(_, value)| matches!(value, Value::Closure(Closure::Native(_))))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect::<HashMap<_, _>>();
+    let mut module = Storage::new();
+    module.global_scope_mut().data = prelude.clone();
+    module.strict = storage.strict || is_strict(&program.statements);
+    std::mem::swap(&mut module.budget, &mut storage.budget);
+    std::mem::swap(&mut module.modules, &mut storage.modules);
+    std::mem::swap(&mut module.hook, &mut storage.hook);
+    module.modules.loading.push(path.to_path_buf());
+    let res = eval_body(
+        &program.functions,
+        &program.statements,
+        &program.spans,
+        &mut module,
+    );
+    module.modules.loading.pop();
+    std::mem::swap(&mut module.budget, &mut storage.budget);
+    std::mem::swap(&mut module.modules, &mut storage.modules);
+    std::mem::swap(&mut module.hook, &mut storage.hook);
+    res?;
+
+    let globals: Globals = Rc::new(RefCell::new(module.scopes.swap_remove(0).into_values()));
+    let env = Env {
+        globals: Rc::downgrade(&globals),
+    };
+    for value in globals.borrow_mut().values_mut() {
+        attach(value, &env);
+    }
+    let exports = globals
+        .borrow()
+        .iter()
+        .filter(|(name, value)| *name != "_" && prelude.get(*name) != Some(value))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    storage.modules.globals.push(globals);
+    Ok(Value::Table(ValuesTable { map: exports }))
+}
+
+impl Evaluate for ImportStatement {
+    fn eval(&self, storage: &mut Storage) -> Result<Value> {
+        let name = binding(self)?;
+        let path = resolve(self)?;
+        let exports = match storage.modules.cache.get(&path) {
+            Some(exports) => exports.clone(),
+            None => {
+                let exports = load(&path, storage)?;
+                storage.modules.cache.insert(path, exports.clone());
+                exports
+            }
+        };
+        storage.define(name, exports.clone());
+        Ok(exports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Runtime;
+
+    fn import(path: &str, name: Option<&str>) -> ImportStatement {
+        ImportStatement {
+            path: path.to_string(),
+            name: name.map(str::to_string),
+            source: "<input>".to_string(),
+        }
+    }
+
+    /// A directory with `files` in it, and a runtime importing from there.
+    fn setup(test: &str, files: &[(&str, &str)]) -> (PathBuf, Runtime) {
+        let dir = env::temp_dir().join(format!("qs-modules-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, src) in files {
+            fs::write(dir.join(name), src).unwrap();
+        }
+        (dir, Runtime::new())
+    }
+
+    fn run(runtime: &mut Runtime, dir: &Path, src: &str) -> Result<Value> {
+        let main = dir.join("main.qs").display().to_string();
+        runtime.run(parse_named(src, &main)?)
+    }
+
+    #[test]
+    fn bindings() {
+        assert_eq!(
+            binding(&import("lib/math.qs", None)),
+            Ok("math".to_string())
+        );
+        assert_eq!(binding(&import("math.qs", Some("m"))), Ok("m".to_string()));
+        assert_eq!(
+            binding(&import("my-lib.qs", None)),
+            Err(Error::Import(
+                "\"my-lib.qs\" isn't a valid name, use `import \"my-lib.qs\" as name`".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn exports_are_namespaced() {
+        let (dir, mut runtime) =
+            setup("exports", &[("math.qs", "pi := 3\nfn double(x) { x * 2 }")]);
+        let res = run(
+            &mut runtime,
+            &dir,
+            "import \"math.qs\"\nmath.double(math.pi)",
+        );
+        assert_eq!(res, Ok(Value::Number(6.0)));
+        assert_eq!(runtime.get_global("pi"), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn modules_are_evaluated_once() {
+        let (dir, mut runtime) = setup("once", &[("counter.qs", "n := 0\nn = n + 1")]);
+        let src = "import \"counter.qs\" as a\nimport \"counter.qs\" as b\na.n + b.n";
+        assert_eq!(run(&mut runtime, &dir, src), Ok(Value::Number(2.0)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn import_errors() {
+        let (dir, mut runtime) = setup(
+            "errors",
+            &[("a.qs", "import \"b.qs\""), ("b.qs", "import \"a.qs\"")],
+        );
+        let Err(Error::Import(cycle)) = run(&mut runtime, &dir, "import \"a.qs\"") else {
+            panic!("expected an import cycle");
+        };
+        assert!(cycle.starts_with("import cycle: "), "{}", cycle);
+        assert!(cycle.ends_with("a.qs"), "{}", cycle);
+        let Err(Error::Import(missing)) = run(&mut runtime, &dir, "import \"none.qs\"") else {
+            panic!("expected a missing module");
+        };
+        assert!(
+            missing.starts_with("module \"none.qs\" not found"),
+            "{}",
+            missing
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
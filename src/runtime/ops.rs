@@ -1,6 +1,6 @@
 use std::cmp::Ordering;
 
-use super::{Evaluate, Storage};
+use super::{destructure, Cell, Error, Evaluate, Result, Storage};
 use crate::parser::*;
 
 pub fn add(lhs: Value, rhs: Value) -> Value {
@@ -8,6 +8,17 @@ pub fn add(lhs: Value, rhs: Value) -> Value {
     match (lhs, rhs) {
         (Number(lhs), Number(rhs)) => Number(lhs + rhs),
         (String(lhs), String(rhs)) => String(lhs + &rhs),
+        (Set(mut lhs), Set(rhs)) => {
+            lhs.elements.extend(rhs.elements);
+            Set(lhs)
+        }
+        (Set(mut lhs), rhs) => match Key::new(&rhs) {
+            Some(key) => {
+                lhs.elements.insert(key);
+                Set(lhs)
+            }
+            None => Nil,
+        },
         (Array(lhs), rhs) => Array(ValuesArray {
             elements: lhs
                 .elements
@@ -36,23 +47,64 @@ pub fn sub(lhs: Value, rhs: Value) -> Value {
     use Value::*;
     match (lhs, rhs) {
         (Number(lhs), Number(rhs)) => Number(lhs - rhs),
+        (Set(mut lhs), Set(rhs)) => {
+            lhs.elements.retain(|key| !rhs.elements.contains(key));
+            Set(lhs)
+        }
+        (Set(mut lhs), rhs) => match Key::new(&rhs) {
+            Some(key) => {
+                lhs.elements.remove(&key);
+                Set(lhs)
+            }
+            None => Nil,
+        },
         _ => Nil,
     }
 }
 
-pub fn mul(lhs: Value, rhs: Value, storage: &mut Storage) -> Value {
+pub fn ampersand(lhs: Value, rhs: Value) -> Value {
     use Value::*;
     match (lhs, rhs) {
+        (Set(mut lhs), Set(rhs)) => {
+            lhs.elements.retain(|key| rhs.elements.contains(key));
+            Set(lhs)
+        }
+        _ => Nil,
+    }
+}
+
+/// Membership: an element of a set or array, a key of a table, a substring of a string.
+pub fn is_in(lhs: Value, rhs: Value) -> Value {
+    use Value::*;
+    match (lhs, rhs) {
+        (lhs, Set(set)) => Boolean(Key::new(&lhs).is_some_and(|key| set.elements.contains(&key))),
+        (lhs, Array(array)) => Boolean(
+            array
+                .elements
+                .into_iter()
+                .any(|element| as_bool(eq(lhs.clone(), element))),
+        ),
+        (String(lhs), Table(table)) => Boolean(table.map.contains_key(&lhs)),
+        (String(lhs), String(rhs)) => Boolean(rhs.contains(&lhs)),
+        (Number(lhs), ExclusiveRange(start, end)) => Boolean(start <= lhs && lhs < end),
+        (Number(lhs), InclusiveRange(start, end)) => Boolean(start <= lhs && lhs <= end),
+        _ => Nil,
+    }
+}
+
+pub fn mul(lhs: Value, rhs: Value, storage: &mut Storage) -> Result<Value> {
+    use Value::*;
+    Ok(match (lhs, rhs) {
         (Number(lhs), Number(rhs)) => Number(lhs * rhs),
         (Array(lhs), Closure(rhs)) => Array(ValuesArray {
             elements: lhs
                 .elements
                 .into_iter()
                 .map(|x| run_closure(rhs.clone(), vec![x.clone()], storage))
-                .collect(),
+                .collect::<Result<_>>()?,
         }),
         _ => Nil,
-    }
+    })
 }
 
 pub fn div(lhs: Value, rhs: Value) -> Value {
@@ -68,7 +120,18 @@ pub fn eq(lhs: Value, rhs: Value) -> Value {
     match (lhs, rhs) {
         (Number(lhs), Number(rhs)) => Boolean(lhs == rhs),
         (String(lhs), String(rhs)) => Boolean(lhs == rhs),
+        (Boolean(lhs), Boolean(rhs)) => Boolean(lhs == rhs),
         (Nil, Nil) => Boolean(true),
+        (Array(lhs), Array(rhs)) => Boolean(
+            lhs.elements.len() == rhs.elements.len()
+                && lhs
+                    .elements
+                    .into_iter()
+                    .zip(rhs.elements)
+                    .all(|(lhs, rhs)| as_bool(eq(lhs, rhs))),
+        ),
+        (Set(lhs), Set(rhs)) => Boolean(lhs == rhs),
+        (UserData(lhs), UserData(rhs)) => Boolean(lhs == rhs),
         _ => Boolean(false),
     }
 }
@@ -268,10 +331,38 @@ pub fn index(value: Value, idx: Value) -> Value {
             Value::String(key) => table.map.get(&key).cloned().unwrap_or(Nil),
             _ => Nil,
         },
+        UserData(data) => match idx {
+            Value::String(key) => data.object.get(&key).unwrap_or(Nil),
+            _ => Nil,
+        },
         _ => Nil,
     }
 }
 
+/// Replaces what `path` leads to inside `value`, an existing array element or a table entry.
+/// `false` if there's nothing there that can be replaced.
+pub fn set_index(value: &mut Value, path: &[Value], new: Value) -> bool {
+    let Some((key, rest)) = path.split_first() else {
+        *value = new;
+        return true;
+    };
+    match (value, key) {
+        (Value::Array(arr), Value::Number(index)) => match to_index(*index, arr.elements.len()) {
+            Some(i) => set_index(&mut arr.elements[i], rest, new),
+            None => false,
+        },
+        (Value::Table(table), Value::String(key)) if rest.is_empty() => {
+            table.map.insert(key.clone(), new);
+            true
+        }
+        (Value::Table(table), Value::String(key)) => match table.map.get_mut(key) {
+            Some(value) => set_index(value, rest, new),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
 pub fn dot_index(value: Value, idx: String) -> Value {
     index(value, Value::String(idx))
 }
@@ -295,6 +386,9 @@ pub fn inclusive_range(lhs: Value, rhs: Value) -> Value {
 pub fn hash(value: Value) -> Value {
     use Value::*;
     match value {
+        Set(set) => Value::Array(ValuesArray {
+            elements: set.sorted().into_iter().map(Key::to_value).collect(),
+        }),
         Table(table) => Value::Array(ValuesArray {
             elements: table.map.keys().cloned().map(Value::String).collect(),
         }),
@@ -330,49 +424,110 @@ pub fn triple_hash(value: Value) -> Value {
     }
 }
 
-pub fn modulo(lhs: Value, rhs: Value, storage: &mut Storage) -> Value {
+pub fn modulo(lhs: Value, rhs: Value, storage: &mut Storage) -> Result<Value> {
     use Value::*;
-    match (lhs, rhs) {
+    Ok(match (lhs, rhs) {
         (Number(lhs), Number(rhs)) => Number(lhs % rhs),
-        (Array(lhs), Closure(rhs)) => Array(ValuesArray {
-            elements: lhs
-                .elements
-                .into_iter()
-                .filter(|x| as_bool(run_closure(rhs.clone(), vec![x.clone()], storage)))
-                .collect(),
-        }),
+        (Array(lhs), Closure(rhs)) => {
+            let mut elements = vec![];
+            for x in lhs.elements {
+                if as_bool(run_closure(rhs.clone(), vec![x.clone()], storage)?) {
+                    elements.push(x);
+                }
+            }
+            Array(ValuesArray { elements })
+        }
         _ => Nil,
-    }
+    })
+}
+
+pub fn run_closure(closure: Closure, args: Vec<Value>, storage: &mut Storage) -> Result<Value> {
+    run_closure_with_refs(closure, args, vec![], storage)
 }
 
-pub fn run_closure(closure: Closure, args: Vec<Value>, storage: &mut Storage) -> Value {
-    match closure {
+/// Like `run_closure`, arguments with a cell are bound as references to it.
+pub fn run_closure_with_refs(
+    closure: Closure,
+    args: Vec<Value>,
+    cells: Vec<Option<Cell>>,
+    storage: &mut Storage,
+) -> Result<Value> {
+    if let Closure::Normal(closure) = &closure {
+        let (min, max) = closure.arity();
+        if storage.strict && (args.len() < min || max.is_some_and(|max| args.len() > max)) {
+            return Err(Error::Arity {
+                expected: if args.len() < min { min } else { max.unwrap() },
+                found: args.len(),
+            });
+        }
+    }
+    storage.budget.enter()?;
+    let res = match closure {
         Closure::Normal(closure) => {
             storage.push_scope();
-            for (name, value) in closure.arguments.iter().zip(args.iter()) {
-                storage.define(name, value.clone());
-            }
-            let res = closure.body.eval(storage);
+            storage.current_scope_mut().env = closure.env.and_then(|env| env.globals.upgrade());
+            let res = bind_arguments(&closure.arguments, args, cells, storage)
+                .and_then(|()| closure.body.eval(storage));
             storage.pop_scope();
             res
         }
         Closure::Native(closure) => (closure.function)(args),
+    };
+    storage.budget.leave();
+    res
+}
+
+fn bind_arguments(
+    parameters: &[Parameter],
+    mut args: Vec<Value>,
+    cells: Vec<Option<Cell>>,
+    storage: &mut Storage,
+) -> Result<()> {
+    for (i, parameter) in parameters.iter().enumerate() {
+        let value = match (&parameter.pattern, &parameter.default) {
+            (Pattern::Rest(_), _) => Value::Array(ValuesArray {
+                elements: args.split_off(i.min(args.len())),
+            }),
+            (Pattern::Binding(name), _) if i < args.len() => match cells.get(i) {
+                Some(Some(cell)) => {
+                    storage.bind(name, cell.clone());
+                    continue;
+                }
+                _ => args[i].clone(),
+            },
+            _ if i < args.len() => args[i].clone(),
+            (_, Some(default)) => default.eval(storage)?,
+            (_, None) => Value::Nil,
+        };
+        destructure(&parameter.pattern, value, storage)?;
     }
+    Ok(())
 }
 
-pub fn at(lhs: Value, rhs: Value, storage: &mut Storage) -> Value {
+pub fn at(lhs: Value, rhs: Value, storage: &mut Storage) -> Result<Value> {
     use Value::*;
     match (lhs, rhs) {
         (Array(mut lhs), Closure(rhs)) => {
+            // sort_by can't bail out early, so keep the first error and ignore the rest
+            let mut error = None;
             lhs.elements.sort_by(|a, b| {
-                let res = run_closure(rhs.clone(), vec![a.clone(), b.clone()], storage);
-                match res {
-                    Value::Number(res) => res.partial_cmp(&0.).unwrap(),
-                    _ => Ordering::Equal,
+                if error.is_some() {
+                    return Ordering::Equal;
+                }
+                match run_closure(rhs.clone(), vec![a.clone(), b.clone()], storage) {
+                    Ok(Value::Number(res)) => res.partial_cmp(&0.).unwrap_or(Ordering::Equal),
+                    Ok(_) => Ordering::Equal,
+                    Err(err) => {
+                        error = Some(err);
+                        Ordering::Equal
+                    }
                 }
             });
-            Array(lhs)
+            match error {
+                Some(err) => Err(err),
+                None => Ok(Array(lhs)),
+            }
         }
-        _ => Nil,
+        _ => Ok(Nil),
     }
 }
@@ -1,16 +1,32 @@
 #![allow(unused)]
 
-use crate::parser::Value;
-use std::collections::HashMap;
+use super::{Assignment, Budget, Frame, History, Hook, Modules, Tracking};
+use crate::parser::{Globals, Span, Value};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// Value shared by every name bound to it with `->`.
+pub type Cell = Rc<RefCell<Value>>;
 
 pub struct Scope {
     pub data: HashMap<String, Value>,
+    /// References, a name is either here or in `data`.
+    pub refs: HashMap<String, Cell>,
+    /// Globals of the module whose function this scope belongs to, looked up after `data`.
+    pub env: Option<Globals>,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Scope {
     pub fn new() -> Self {
         Self {
             data: HashMap::new(),
+            refs: HashMap::new(),
+            env: None,
         }
     }
 
@@ -25,32 +41,95 @@ impl Scope {
     where
         S: AsRef<str>,
     {
-        self.data.get(name.as_ref()).cloned()
+        let name = name.as_ref();
+        if let Some(cell) = self.refs.get(name) {
+            return Some(cell.borrow().clone());
+        }
+        match (self.data.get(name), &self.env) {
+            (Some(value), _) => Some(value.clone()),
+            (None, Some(env)) => env.borrow().get(name).cloned(),
+            (None, None) => None,
+        }
     }
 
+    /// Writes through references and to a module global if there's one with this name,
+    /// defines a local otherwise.
     pub fn set<S>(&mut self, name: S, value: Value)
     where
         S: AsRef<str>,
     {
-        self.data.insert(name.as_ref().to_string(), value);
+        let name = name.as_ref();
+        if let Some(cell) = self.refs.get(name) {
+            *cell.borrow_mut() = value;
+            return;
+        }
+        if let Some(env) = &self.env {
+            if !self.data.contains_key(name) && env.borrow().contains_key(name) {
+                env.borrow_mut().insert(name.to_string(), value);
+                return;
+            }
+        }
+        self.data.insert(name.to_string(), value);
     }
 
     pub fn has<S>(&self, name: S) -> bool
     where
         S: AsRef<str>,
     {
-        self.data.contains_key(name.as_ref())
+        let name = name.as_ref();
+        self.data.contains_key(name)
+            || self.refs.contains_key(name)
+            || self
+                .env
+                .as_ref()
+                .is_some_and(|env| env.borrow().contains_key(name))
+    }
+
+    /// Names defined in this scope, not counting module globals.
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.data.keys().chain(self.refs.keys())
+    }
+
+    /// Every variable with its current value, references included.
+    pub fn into_values(self) -> HashMap<String, Value> {
+        let mut values = self.data;
+        for (name, cell) in self.refs {
+            let value = cell.borrow().clone();
+            values.insert(name, value);
+        }
+        values
     }
 }
 
 pub struct Storage {
     pub scopes: Vec<Scope>,
+    pub budget: Budget,
+    pub modules: Modules,
+    /// Set by `--strict` or a `"use strict"` first statement.
+    pub strict: bool,
+    /// Shared so builtins can read it.
+    pub history: Rc<RefCell<History>>,
+    pub hook: Option<Box<dyn Hook>>,
+    /// Named calls in progress, only kept while there's a hook.
+    pub frames: Vec<Frame>,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Storage {
     pub fn new() -> Self {
         Self {
             scopes: vec![Scope::new()],
+            budget: Budget::default(),
+            modules: Modules::default(),
+            strict: false,
+            history: Rc::default(),
+            hook: None,
+            frames: vec![],
         }
     }
 
@@ -62,11 +141,83 @@ impl Storage {
         self.scopes.pop();
     }
 
+    /// Where the next `set` or `define` happens, for the history.
+    pub fn at(&mut self, span: &Span) {
+        let mut history = self.history.borrow_mut();
+        if history.tracking != Tracking::Off {
+            history.span = Some(span.clone());
+        }
+    }
+
+    fn record(&self, name: &str, value: &Value) {
+        let mut history = self.history.borrow_mut();
+        // kept for the other names the statement binds
+        let span = history.span.clone();
+        if history.tracks(name) {
+            let assignment = Assignment {
+                value: value.clone(),
+                span,
+                step: self.budget.total_steps(),
+            };
+            history.record(name, assignment);
+        }
+    }
+
     pub fn define<S>(&mut self, name: S, value: Value)
     where
         S: AsRef<str>,
     {
-        self.scopes.last_mut().unwrap().set(name, value);
+        self.record(name.as_ref(), &value);
+        let scope = self.scopes.last_mut().unwrap();
+        match scope.refs.get(name.as_ref()) {
+            Some(cell) => *cell.borrow_mut() = value,
+            None => {
+                scope.data.insert(name.as_ref().to_string(), value);
+            }
+        }
+    }
+
+    /// The cell behind `name`, turning the variable into a reference if it isn't one yet.
+    /// Unknown names are defined as nil in the current scope.
+    pub fn reference<S>(&mut self, name: S) -> Cell
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+        let scope = match self.scopes.iter().rposition(|scope| scope.has(name)) {
+            Some(i) => &mut self.scopes[i],
+            None => self.scopes.last_mut().unwrap(),
+        };
+        if let Some(cell) = scope.refs.get(name) {
+            return cell.clone();
+        }
+        let value = scope.get(name);
+        let cell = Rc::new(RefCell::new(value));
+        scope.data.remove(name);
+        scope.refs.insert(name.to_string(), cell.clone());
+        cell
+    }
+
+    /// The cell behind `name` if it's a reference.
+    pub fn cell<S>(&self, name: S) -> Option<Cell>
+    where
+        S: AsRef<str>,
+    {
+        self.scopes
+            .iter()
+            .rev()
+            .find(|scope| scope.has(&name))
+            .and_then(|scope| scope.refs.get(name.as_ref()).cloned())
+    }
+
+    /// Makes `name` in the current scope another name for `cell`.
+    pub fn bind<S>(&mut self, name: S, cell: Cell)
+    where
+        S: AsRef<str>,
+    {
+        let scope = self.scopes.last_mut().unwrap();
+        scope.data.remove(name.as_ref());
+        scope.refs.insert(name.as_ref().to_string(), cell);
     }
 
     pub fn get<S>(&self, name: S) -> Value
@@ -97,6 +248,7 @@ impl Storage {
     where
         S: AsRef<str>,
     {
+        self.record(name.as_ref(), &value);
         for scope in self.scopes.iter_mut().rev() {
             if scope.has(&name) {
                 scope.set(name, value);
@@ -118,6 +270,19 @@ impl Storage {
         false
     }
 
+    /// Names visible from the current scope, inner scopes first, may repeat.
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.scopes.iter().rev().flat_map(Scope::names)
+    }
+
+    /// Globals of the module whose function is running, if any.
+    pub fn env(&self) -> Option<&Globals> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.env.as_ref())
+    }
+
     pub fn current_scope(&self) -> &Scope {
         self.scopes.last().unwrap()
     }
@@ -134,3 +299,52 @@ impl Storage {
         self.scopes.first_mut().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references_share_a_value() {
+        let mut storage = Storage::new();
+        storage.define("a", Value::Number(1.0));
+        let cell = storage.reference("a");
+        storage.push_scope();
+        storage.bind("b", cell.clone());
+        storage.set("b", Value::Number(2.0));
+        assert_eq!(storage.get("a"), Value::Number(2.0));
+        storage.pop_scope();
+        storage.set("a", Value::Number(3.0));
+        assert_eq!(*cell.borrow(), Value::Number(3.0));
+    }
+
+    #[test]
+    fn referencing_twice_gives_the_same_cell() {
+        let mut storage = Storage::new();
+        storage.define("a", Value::Nil);
+        let first = storage.reference("a");
+        let second = storage.reference("a");
+        assert!(Rc::ptr_eq(&first, &second));
+        assert!(storage
+            .cell("a")
+            .is_some_and(|cell| Rc::ptr_eq(&cell, &first)));
+    }
+
+    #[test]
+    fn unknown_names_become_nil_references() {
+        let mut storage = Storage::new();
+        assert!(storage.cell("x").is_none());
+        let cell = storage.reference("x");
+        assert_eq!(*cell.borrow(), Value::Nil);
+        assert!(storage.has("x"));
+    }
+
+    #[test]
+    fn defining_again_writes_through() {
+        let mut storage = Storage::new();
+        storage.define("a", Value::Number(1.0));
+        let cell = storage.reference("a");
+        storage.define("a", Value::Number(5.0));
+        assert_eq!(*cell.borrow(), Value::Number(5.0));
+    }
+}
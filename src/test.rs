@@ -0,0 +1,160 @@
+use qs::{
+    parser::{self, Program, Test},
+    runtime::{Result, Runtime},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+/// Runs every `test` block in `paths`, each in a runtime of its own that first runs the rest of
+/// its file. Exits with 1 if any failed.
+pub fn run(paths: &[String], make_runtime: impl Fn() -> Runtime) {
+    let mut files = vec![];
+    match paths.is_empty() {
+        true => collect(Path::new("."), &mut files),
+        false => paths
+            .iter()
+            .for_each(|path| collect(Path::new(path), &mut files)),
+    }
+
+    let (mut passed, mut failures) = (0, vec![]);
+    for file in files {
+        let name = file
+            .strip_prefix(".")
+            .unwrap_or(&file)
+            .display()
+            .to_string();
+        let src = fs::read_to_string(&file).unwrap_or_else(|e| {
+            eprintln!("can't read {}: {}", name, e);
+            exit(1);
+        });
+        let program = match parser::parse_named(&src, &name) {
+            Ok(program) => program,
+            Err(e) => {
+                println!("{} ... FAILED", name);
+                failures.push((name, e.to_string()));
+                continue;
+            }
+        };
+        for test in &program.tests {
+            let title = format!("{}:{} {}", name, test.span.line, test.name);
+            match run_test(make_runtime(), &program, test) {
+                Ok(()) => {
+                    println!("test {} ... ok", title);
+                    passed += 1;
+                }
+                Err(e) => {
+                    println!("test {} ... FAILED", title);
+                    failures.push((title, e.to_string()));
+                }
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (title, error) in &failures {
+            println!("\n---- {} ----\n{}", title, error);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        passed,
+        failures.len()
+    );
+    if !failures.is_empty() {
+        exit(1);
+    }
+}
+
+/// `.qs` files under `path` in name order, skipping hidden directories and `target`.
+fn collect(path: &Path, files: &mut Vec<PathBuf>) {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return;
+    }
+    let mut entries = fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_else(|_| vec![]);
+    entries.sort();
+    for entry in entries {
+        let name = entry
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if entry.is_dir() {
+            if !name.starts_with('.') && name != "target" {
+                collect(&entry, files);
+            }
+        } else if entry.extension().is_some_and(|extension| extension == "qs") {
+            files.push(entry);
+        }
+    }
+}
+
+/// Runs the top level of `program`, then the body of `test`.
+fn run_test(mut runtime: Runtime, program: &Program, test: &Test) -> Result<()> {
+    runtime.run(Program {
+        tests: vec![],
+        ..program.clone()
+    })?;
+    runtime.run(Program {
+        statements: test.body.statements.clone(),
+        functions: test.body.functions.clone(),
+        spans: test.body.spans.clone(),
+        tests: vec![],
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qs::runtime::Error;
+    use std::env;
+
+    #[test]
+    fn tests_run_after_the_top_level() {
+        let src =
+            "x := 1\ntest \"x\" {\n  assert(x == 1)\n  x = 2\n}\ntest \"y\" {\n  assert(x == 2)\n}";
+        let program = parser::parse(src).unwrap();
+        assert_eq!(program.tests[0].name, "x");
+        assert_eq!(
+            run_test(Runtime::with_std(), &program, &program.tests[0]),
+            Ok(())
+        );
+        // each test starts over from the top level
+        assert_eq!(
+            run_test(Runtime::with_std(), &program, &program.tests[1]),
+            Err(Error::Assertion("condition is not true".to_string()))
+        );
+    }
+
+    #[test]
+    fn collects_scripts_in_name_order() {
+        let root = env::temp_dir().join(format!("qs-collect-{}", std::process::id()));
+        for file in [
+            "b.qs",
+            "a.qs",
+            "notes.txt",
+            "sub/c.qs",
+            ".git/d.qs",
+            "target/e.qs",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let mut files = vec![];
+        collect(&root, &mut files);
+        let names = files
+            .iter()
+            .map(|file| file.strip_prefix(&root).unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a.qs", "b.qs", "sub/c.qs"]);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
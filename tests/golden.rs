@@ -0,0 +1,151 @@
+//! Runs every `.qs` file under `tests/` and compares what it prints with the `.out` file next
+//! to it. Failed runs also record their standard error and exit code. `tests/modules` only
+//! has modules the scripts import and isn't run.
+//!
+//! `cargo test --test golden -- --bless` rewrites the `.out` files instead, other arguments
+//! only run the scripts whose path contains one of them.
+//!
+//! A first line like `// args: --debug-level 2` runs the script with those arguments, in place
+//! of the defaults they name. Standard error is then recorded even if the run succeeds, with
+//! the time `??` prints replaced by `+Xms`.
+
+use regex::Regex;
+use similar::{ChangeTag, TextDiff};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{exit, Command},
+};
+
+fn main() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let bless = args.iter().any(|arg| arg == "--bless");
+    let filters = args
+        .iter()
+        .filter(|arg| !arg.starts_with('-'))
+        .collect::<Vec<_>>();
+
+    let mut scripts = vec![];
+    collect(&root, &root.join("modules"), &mut scripts);
+    // debug builds write ast.json where they run, keep it out of the tree
+    let scratch = env::temp_dir().join("qs-golden");
+    fs::create_dir_all(&scratch).unwrap();
+
+    let (mut passed, mut failed) = (0, 0);
+    for script in scripts {
+        let name = Path::new("tests")
+            .join(script.strip_prefix(&root).unwrap())
+            .display()
+            .to_string();
+        if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+            continue;
+        }
+        let actual = run(&script, &scratch).replace(&root.display().to_string(), "tests");
+        let expected_path = script.with_extension("out");
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+            println!("blessed {}", name);
+            continue;
+        }
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => {
+                println!("golden {} ... ok", name);
+                passed += 1;
+            }
+            Ok(expected) => {
+                println!("golden {} ... FAILED\n{}", name, diff(&expected, &actual));
+                failed += 1;
+            }
+            Err(_) => {
+                println!("golden {} ... FAILED, no .out file, run with --bless", name);
+                failed += 1;
+            }
+        }
+    }
+
+    if !bless {
+        println!("\ngolden result: {} passed; {} failed", passed, failed);
+    }
+    if failed > 0 {
+        exit(1);
+    }
+}
+
+/// `.qs` files under `dir` in name order, leaving out the `skip` directory.
+fn collect(dir: &Path, skip: &Path, scripts: &mut Vec<PathBuf>) {
+    let mut entries = fs::read_dir(dir)
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            if entry != skip {
+                collect(&entry, skip, scripts);
+            }
+        } else if entry.extension().is_some_and(|extension| extension == "qs") {
+            scripts.push(entry);
+        }
+    }
+}
+
+/// Arguments of the `// args:` first line of `script`, if it has one.
+fn script_args(script: &Path) -> Option<Vec<String>> {
+    let src = fs::read_to_string(script).unwrap();
+    let args = src.lines().next()?.strip_prefix("// args:")?;
+    Some(args.split_whitespace().map(str::to_string).collect())
+}
+
+/// Standard output of `script`, followed by its standard error and exit code if it failed.
+/// Scripts with their own arguments always get their standard error recorded.
+fn run(script: &Path, dir: &Path) -> String {
+    let extra = script_args(script);
+    let defaults = [
+        ("--color", "never"),
+        ("--debug-level", "0"),
+        ("--timeout", "10000"),
+    ];
+    let args = defaults
+        .into_iter()
+        .filter(|(flag, _)| !extra.iter().flatten().any(|arg| arg == flag))
+        .flat_map(|(flag, value)| [flag.to_string(), value.to_string()])
+        .chain(extra.iter().flatten().cloned());
+    let output = Command::new(env!("CARGO_BIN_EXE_qs"))
+        .args(args)
+        .arg(script)
+        .current_dir(dir)
+        .env_remove("QS_PATH")
+        .output()
+        .unwrap();
+    let mut actual = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.status.success() || extra.is_some() {
+        if !actual.is_empty() && !actual.ends_with('\n') {
+            actual.push('\n');
+        }
+        // `??` prints how long the script has run
+        let elapsed = Regex::new(r"\+\d+\.\dms\]").unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        actual += &format!("--- stderr\n{}", elapsed.replace_all(&stderr, "+Xms]"));
+    }
+    if !output.status.success() {
+        actual += &format!("--- exit code {}\n", output.status.code().unwrap_or(-1));
+    }
+    actual
+}
+
+fn diff(expected: &str, actual: &str) -> String {
+    TextDiff::from_lines(expected, actual)
+        .iter_all_changes()
+        .map(|change| {
+            let sign = match change.tag() {
+                ChangeTag::Delete => "-",
+                ChangeTag::Insert => "+",
+                ChangeTag::Equal => " ",
+            };
+            format!("{}{}", sign, change.value().trim_end_matches('\n'))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
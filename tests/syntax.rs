@@ -0,0 +1,39 @@
+//! Runs the examples of `SYNTAX`, as a script and with `qs test`, so they keep working.
+
+use std::{
+    env,
+    path::Path,
+    process::{Command, Output},
+};
+
+fn qs(args: &[&str]) -> Output {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    Command::new(env!("CARGO_BIN_EXE_qs"))
+        .args(args)
+        .arg(root.join("SYNTAX"))
+        // debug builds write ast.json where they run
+        .current_dir(env::temp_dir())
+        // the `import "strings.qs"` example
+        .env("QS_PATH", root.join("tests").join("modules"))
+        .output()
+        .unwrap()
+}
+
+fn check(output: Output) {
+    assert!(
+        output.status.success(),
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn examples_run() {
+    check(qs(&["--color", "never", "--debug-level", "0"]));
+}
+
+#[test]
+fn example_tests_pass() {
+    check(qs(&["test"]));
+}